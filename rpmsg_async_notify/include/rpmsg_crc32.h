@@ -0,0 +1,72 @@
+/*
+ * Checksum framing of rpmsg messages.
+ * Counterpart of rpmsg_async_notify::checksum on the Linux side, enabled
+ * there with ChannelConfig::checksum or set_checksum on the endpoint.
+ *
+ * Every rpmsg message is followed by the CRC32 of the message, stored as
+ * 4 little endian bytes. The CRC is the IEEE 802.3 one used by zlib
+ * (reflected polynomial 0xedb88320, initial value and final xor 0xffffffff),
+ * its check value is rpmsg_crc32("123456789", 9) == 0xcbf43926.
+ *
+ * - the sender appends the CRC with rpmsg_crc32_append.
+ * - the receiver checks it with rpmsg_crc32_verify and drops the message
+ *   on a mismatch, Linux reports ChannelError::ChecksumMismatch.
+ * - both sides have to enable the framing, an endpoint doesn't negotiate it.
+ *
+ * The CRC is computed bit by bit, without a table, to keep the firmware small.
+ */
+#ifndef RPMSG_CRC32_H
+#define RPMSG_CRC32_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define RPMSG_CRC32_LEN 4
+
+static inline uint32_t rpmsg_crc32(const uint8_t *data, size_t len)
+{
+	uint32_t crc = 0xffffffffu;
+
+	for (size_t i = 0; i < len; i++) {
+		crc ^= data[i];
+		for (int bit = 0; bit < 8; bit++)
+			crc = (crc >> 1) ^ (0xedb88320u & -(crc & 1u));
+	}
+	return ~crc;
+}
+
+/*
+ * Append the CRC of the len bytes of buf to buf, which holds capacity bytes.
+ * Return the size of the framed message, or -1 when there is no room.
+ */
+static inline int rpmsg_crc32_append(uint8_t *buf, size_t len, size_t capacity)
+{
+	uint32_t crc;
+
+	if (capacity < RPMSG_CRC32_LEN || len > capacity - RPMSG_CRC32_LEN)
+		return -1;
+	crc = rpmsg_crc32(buf, len);
+	buf[len] = crc & 0xff;
+	buf[len + 1] = (crc >> 8) & 0xff;
+	buf[len + 2] = (crc >> 16) & 0xff;
+	buf[len + 3] = (crc >> 24) & 0xff;
+	return (int)(len + RPMSG_CRC32_LEN);
+}
+
+/*
+ * Check the CRC at the end of the len bytes of buf.
+ * Return the size of the message in front of the CRC, or -1 on a mismatch.
+ */
+static inline int rpmsg_crc32_verify(const uint8_t *buf, size_t len)
+{
+	uint32_t received;
+
+	if (len < RPMSG_CRC32_LEN)
+		return -1;
+	len -= RPMSG_CRC32_LEN;
+	received = (uint32_t)buf[len] | (uint32_t)buf[len + 1] << 8 |
+		   (uint32_t)buf[len + 2] << 16 | (uint32_t)buf[len + 3] << 24;
+	return received == rpmsg_crc32(buf, len) ? (int)len : -1;
+}
+
+#endif /* RPMSG_CRC32_H */
@@ -0,0 +1,42 @@
+/*
+ * Fragmentation protocol for messages larger than one rpmsg buffer.
+ * Counterpart of rpmsg_async_notify::fragment on the Linux side.
+ *
+ * Every rpmsg message starts with struct rpmsg_fragment_header, followed by
+ * payload_len bytes of the logical message. All fields are little endian.
+ *
+ * - message_id is shared by all fragments of a message and incremented
+ *   (wrapping) for every new message.
+ * - fragment_index counts from 0 to fragment_count - 1.
+ * - a message is complete once all fragment_count fragments arrived, the
+ *   payloads are concatenated in fragment_index order.
+ * - an empty message is sent as a single fragment with payload_len 0.
+ * - the receiver drops incomplete messages after a timeout (500 ms on Linux).
+ * - Linux refuses messages over 64 KiB and more than 8 incomplete messages at
+ *   once by default.
+ * - when the endpoint checksums its messages, the checksum framing takes its
+ *   bytes out of the buffer too: fragments carry at most
+ *   RPMSG_FRAGMENT_PAYLOAD_MAX_SIZE minus the framing overhead.
+ */
+#ifndef RPMSG_FRAGMENT_H
+#define RPMSG_FRAGMENT_H
+
+#include <stdint.h>
+
+/* 512 bytes buffer minus the 16 bytes rpmsg header */
+#define RPMSG_FRAGMENT_BUFF_SIZE 496
+#define RPMSG_FRAGMENT_HEADER_LEN 8
+#define RPMSG_FRAGMENT_PAYLOAD_MAX_SIZE \
+	(RPMSG_FRAGMENT_BUFF_SIZE - RPMSG_FRAGMENT_HEADER_LEN)
+
+struct rpmsg_fragment_header {
+	uint16_t message_id;
+	uint16_t fragment_index;
+	uint16_t fragment_count;
+	uint16_t payload_len;
+} __attribute__((packed));
+
+_Static_assert(sizeof(struct rpmsg_fragment_header) == RPMSG_FRAGMENT_HEADER_LEN,
+	       "rpmsg_fragment_header must be 8 bytes");
+
+#endif /* RPMSG_FRAGMENT_H */
@@ -2,9 +2,9 @@ VSCODE_WS="$1"
 SSH_REMOTE="$2"
 GDBPORT="$3"
 
-APP="signal_hook"
+APP="rpmsg-bench"
 TARGET_ARCH="aarch64-unknown-linux-gnu"
-BUILD_BIN_FILE="${VSCODE_WS}/target/${TARGET_ARCH}/debug/examples/${APP}"
+BUILD_BIN_FILE="${VSCODE_WS}/target/${TARGET_ARCH}/debug/${APP}"
 TARGET_USER="root"
 TARGET_BIN_FILE="/home/root/${APP}"
 TARGET_CWD="/home/root"
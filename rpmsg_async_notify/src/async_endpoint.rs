@@ -0,0 +1,330 @@
+use crate::channel::{AbstractRPMsgChannel, ChannelError, OctRPMsgChannel};
+use crate::MAX_RPMSG_BUFF_SIZE;
+use futures_core::Stream;
+use futures_sink::Sink;
+use std::io;
+use std::os::unix::prelude::AsRawFd;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// an rpmsg endpoint driven by the tokio reactor
+/// every read returns exactly one message and every write sends exactly one message,
+/// the descriptor of the wrapped channel must be in non-blocking mode
+pub struct AsyncRPMsgEndpoint<C = OctRPMsgChannel>
+where
+    C: AbstractRPMsgChannel + AsRawFd,
+{
+    inner: AsyncFd<C>,
+    // message accepted by Sink::start_send but not written yet
+    pending: Option<Vec<u8>>,
+}
+
+/// the channel reports WouldBlock when no message is pending or the remote is not able to take one
+fn is_would_block(error: &ChannelError) -> bool {
+    matches!(error, ChannelError::WouldBlock { .. })
+}
+
+fn into_io_error(error: ChannelError) -> io::Error {
+    io::Error::other(error.to_string())
+}
+
+impl<C> AsyncRPMsgEndpoint<C>
+where
+    C: AbstractRPMsgChannel + AsRawFd,
+{
+    /// register the channel to the reactor of the current tokio runtime
+    pub fn new(channel: C) -> Result<Self, ChannelError> {
+        let inner = AsyncFd::new(channel).map_err(|e| ChannelError::IOError {
+            error: format!("{:?}", e),
+        })?;
+        Ok(AsyncRPMsgEndpoint {
+            inner,
+            pending: None,
+        })
+    }
+
+    /// the wrapped channel
+    pub fn get_ref(&self) -> &C {
+        self.inner.get_ref()
+    }
+
+    /// deregister from the reactor and give back the wrapped channel
+    pub fn into_inner(self) -> C {
+        self.inner.into_inner()
+    }
+
+    /// wait until a message arrives and return it
+    /// an empty message is returned once the remote hung up
+    pub async fn recv(&mut self) -> Result<Vec<u8>, ChannelError> {
+        loop {
+            let mut guard = self
+                .inner
+                .readable_mut()
+                .await
+                .map_err(|e| ChannelError::IOError {
+                    error: format!("{:?}", e),
+                })?;
+            match guard.get_inner_mut().read(MAX_RPMSG_BUFF_SIZE as usize) {
+                Err(e) if is_would_block(&e) => guard.clear_ready(),
+                result => return result,
+            }
+        }
+    }
+
+    /// wait until the endpoint is able to take the message and send it
+    pub async fn send(&mut self, message: &[u8]) -> Result<(), ChannelError> {
+        loop {
+            let mut guard = self
+                .inner
+                .writable_mut()
+                .await
+                .map_err(|e| ChannelError::IOError {
+                    error: format!("{:?}", e),
+                })?;
+            match guard.get_inner_mut().send(message) {
+                Err(e) if is_would_block(&e) => guard.clear_ready(),
+                result => return result,
+            }
+        }
+    }
+
+    fn poll_recv(
+        &mut self,
+        cx: &mut Context<'_>,
+        capacity: usize,
+    ) -> Poll<Result<Vec<u8>, ChannelError>> {
+        loop {
+            let mut guard = match self.inner.poll_read_ready_mut(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(e)) => {
+                    return Poll::Ready(Err(ChannelError::IOError {
+                        error: format!("{:?}", e),
+                    }))
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            match guard.get_inner_mut().read(capacity) {
+                Err(e) if is_would_block(&e) => guard.clear_ready(),
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    fn poll_send(
+        &mut self,
+        cx: &mut Context<'_>,
+        message: &[u8],
+    ) -> Poll<Result<(), ChannelError>> {
+        loop {
+            let mut guard = match self.inner.poll_write_ready_mut(cx) {
+                Poll::Ready(Ok(guard)) => guard,
+                Poll::Ready(Err(e)) => {
+                    return Poll::Ready(Err(ChannelError::IOError {
+                        error: format!("{:?}", e),
+                    }))
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            match guard.get_inner_mut().send(message) {
+                Err(e) if is_would_block(&e) => guard.clear_ready(),
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    // write out the message accepted by start_send, if any
+    fn poll_flush_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), ChannelError>> {
+        if let Some(message) = self.pending.take() {
+            match self.poll_send(cx, &message) {
+                Poll::Pending => {
+                    self.pending = Some(message);
+                    return Poll::Pending;
+                }
+                Poll::Ready(result) => return Poll::Ready(result),
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<C> Stream for AsyncRPMsgEndpoint<C>
+where
+    C: AbstractRPMsgChannel + AsRawFd + Unpin,
+{
+    type Item = Result<Vec<u8>, ChannelError>;
+
+    /// the stream ends when the remote hangs up, errors are handed to the caller to decide
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .poll_recv(cx, MAX_RPMSG_BUFF_SIZE as usize)
+            .map(|result| match result {
+                // why: an empty read is the end of file, the remote hung up
+                Ok(message) if message.is_empty() => None,
+                result => Some(result),
+            })
+    }
+}
+
+impl<C> Sink<Vec<u8>> for AsyncRPMsgEndpoint<C>
+where
+    C: AbstractRPMsgChannel + AsRawFd + Unpin,
+{
+    type Error = ChannelError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_flush_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<u8>) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        debug_assert!(
+            this.pending.is_none(),
+            "start_send called before poll_ready"
+        );
+        this.pending = Some(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_flush_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_flush_pending(cx)
+    }
+}
+
+impl<C> AsyncRead for AsyncRPMsgEndpoint<C>
+where
+    C: AbstractRPMsgChannel + AsRawFd + Unpin,
+{
+    /// read one message, fail when it doesn't fit in the remaining space of buf
+    /// nothing is read into a full buf, and nothing is written to buf at the end of file
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let capacity = buf.remaining();
+        // why: a read would take a whole message and drop it, there is no room for any byte
+        if capacity == 0 {
+            return Poll::Ready(Ok(()));
+        }
+        match self.get_mut().poll_recv(cx, capacity) {
+            Poll::Ready(Ok(message)) => {
+                buf.put_slice(&message);
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(into_io_error(e))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<C> AsyncWrite for AsyncRPMsgEndpoint<C>
+where
+    C: AbstractRPMsgChannel + AsRawFd + Unpin,
+{
+    /// send the whole buf as one message
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_send(cx, buf)
+            .map(|result| result.map(|_| buf.len()).map_err(into_io_error))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::LoopbackRPMsgChannel;
+    use futures::{SinkExt, StreamExt};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn send_and_recv_keep_message_boundaries() {
+        let (host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut host = AsyncRPMsgEndpoint::new(host).unwrap();
+        let mut remote = AsyncRPMsgEndpoint::new(remote).unwrap();
+
+        let echo = tokio::spawn(async move {
+            for _ in 0..2 {
+                let message = remote.recv().await.unwrap();
+                remote.send(&message).await.unwrap();
+            }
+        });
+        host.send(b"first").await.unwrap();
+        host.send(b"second message").await.unwrap();
+        assert_eq!(host.recv().await.unwrap(), b"first");
+        assert_eq!(host.recv().await.unwrap(), b"second message");
+        echo.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stream_and_sink() {
+        let (host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut host = AsyncRPMsgEndpoint::new(host).unwrap();
+        let mut remote = AsyncRPMsgEndpoint::new(remote).unwrap();
+
+        for i in 0..10u8 {
+            host.feed(vec![i; i as usize + 1]).await.unwrap();
+        }
+        SinkExt::flush(&mut host).await.unwrap();
+        for i in 0..10u8 {
+            let message = remote.next().await.unwrap().unwrap();
+            assert_eq!(message, vec![i; i as usize + 1]);
+        }
+    }
+
+    #[tokio::test]
+    async fn async_read_and_write_move_one_message() {
+        let (host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut host = AsyncRPMsgEndpoint::new(host).unwrap();
+        let mut remote = AsyncRPMsgEndpoint::new(remote).unwrap();
+
+        assert_eq!(host.write(b"ping").await.unwrap(), 4);
+        let mut buf = [0u8; 16];
+        let size = remote.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..size], b"ping");
+
+        // a message larger than the buffer is reported instead of silently truncated
+        host.write_all(&[1u8; 32]).await.unwrap();
+        assert!(remote.read(&mut buf).await.is_err());
+
+        // no room reads nothing, the message stays for the next read
+        host.write_all(b"kept").await.unwrap();
+        assert_eq!(remote.read(&mut buf[..0]).await.unwrap(), 0);
+        let size = remote.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..size], b"kept");
+    }
+
+    #[tokio::test]
+    async fn hang_up_ends_the_stream() {
+        let (host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut host = AsyncRPMsgEndpoint::new(host).unwrap();
+        let mut remote = AsyncRPMsgEndpoint::new(remote).unwrap();
+
+        host.send(b"last").await.unwrap();
+        host.send(b"words").await.unwrap();
+        drop(host);
+        assert_eq!(remote.next().await, Some(Ok(b"last".to_vec())));
+        let mut buf = [0u8; 16];
+        let size = remote.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..size], b"words");
+        assert_eq!(remote.next().await, None);
+        assert_eq!(remote.read(&mut buf).await.unwrap(), 0);
+    }
+}
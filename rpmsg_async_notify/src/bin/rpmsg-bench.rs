@@ -0,0 +1,512 @@
+use bincode::{deserialize, serialize, serialized_size};
+use nix::sys::utsname::uname;
+use rpmsg_async_notify::channel::{
+    AbstractRPMsgChannel, ChannelConfig, ChannelError, RPMsgEndpoint,
+};
+use rpmsg_async_notify::echo::{EchoConfig, EchoResponder};
+use rpmsg_async_notify::latency::{ExportFormat, LatencyRecorder, LatencySummary};
+use rpmsg_async_notify::loopback::LoopbackRPMsgChannel;
+use rpmsg_async_notify::notify::{MessageSink, NotificationKind};
+use rpmsg_async_notify::pool::{BufferPool, LeasedBuffer};
+use rpmsg_async_notify::remote_proc::{RemoteprocManager, RemoteprocState};
+use rpmsg_async_notify::{prepare_environment, Payload, PreparedEndpoint, MAX_RPMSG_BUFF_SIZE};
+use serde::Serialize;
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::os::unix::prelude::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// how long the remote processor may take to boot the firmware
+const REMOTEPROC_START_TIMEOUT: Duration = Duration::from_secs(5);
+/// replies received and not recorded yet, more than a few means the recording falls behind
+/// and the receive thread leaves the next replies in the channel until one is recorded
+const REPLY_BUFFERS: usize = 64;
+
+const USAGE: &str = "usage: rpmsg-bench [options]
+    --strategy <epoll|sigio|blocking>   how replies are noticed (default epoll)
+    --count <n>                         payloads measured (default 1000)
+    --size <bytes>                      data bytes in every payload (default 5)
+    --firmware <name>                   firmware echoing the payloads (default echo_test.elf)
+    --remoteproc <id>                   remote processor running the firmware (default remoteproc0)
+    --warmup <n>                        payloads sent before measuring (default 100)
+    --timeout <ms>                      how long to wait for every reply (default 1000)
+    --output <path>                     export to .tsv, .csv or .json (default none)
+    --loopback                          echo in-process over a loopback channel, no firmware needed
+    --echo-delay-us <us>                delay of the in-process echo (default 0)
+    --echo-jitter-us <us>               jitter of the in-process echo (default 0)
+    --echo-drop-rate <0..1>             share of payloads the in-process echo drops (default 0)";
+
+/// how one run is set up, reported along with the result so runs can be compared
+#[derive(Debug, Clone, Serialize)]
+struct BenchOptions {
+    strategy: NotificationKind,
+    count: usize,
+    size: usize,
+    firmware: String,
+    remoteproc: String,
+    warmup: usize,
+    timeout_ms: u64,
+    #[serde(skip)]
+    output: Option<PathBuf>,
+    loopback: bool,
+    echo_delay_us: u64,
+    echo_jitter_us: u64,
+    echo_drop_rate: f64,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            strategy: NotificationKind::Epoll,
+            count: 1000,
+            size: 5,
+            firmware: "echo_test.elf".to_string(),
+            remoteproc: "remoteproc0".to_string(),
+            warmup: 100,
+            timeout_ms: 1000,
+            output: None,
+            loopback: false,
+            echo_delay_us: 0,
+            echo_jitter_us: 0,
+            echo_drop_rate: 0.0,
+        }
+    }
+}
+
+/// what the command line asks for
+#[derive(Debug)]
+enum Invocation {
+    Bench(BenchOptions),
+    Help,
+}
+
+impl BenchOptions {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Invocation, String> {
+        let mut options = BenchOptions::default();
+        while let Some(flag) = args.next() {
+            if flag == "--help" || flag == "-h" {
+                return Ok(Invocation::Help);
+            }
+            if flag == "--loopback" {
+                options.loopback = true;
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value of {}", flag))?;
+            match flag.as_str() {
+                "--strategy" => {
+                    options.strategy = value.parse()?;
+                }
+                "--count" => options.count = parse_number(&flag, &value)?,
+                "--size" => options.size = parse_number(&flag, &value)?,
+                "--firmware" => options.firmware = value,
+                "--remoteproc" => options.remoteproc = value,
+                "--warmup" => options.warmup = parse_number(&flag, &value)?,
+                "--timeout" => options.timeout_ms = parse_number(&flag, &value)? as u64,
+                "--output" => {
+                    let path = PathBuf::from(value);
+                    if ExportFormat::from_path(&path).is_none() {
+                        return Err(format!(
+                            "can't tell the format of {:?}, use .tsv, .csv or .json",
+                            path
+                        ));
+                    }
+                    options.output = Some(path);
+                }
+                "--echo-delay-us" => options.echo_delay_us = parse_number(&flag, &value)? as u64,
+                "--echo-jitter-us" => options.echo_jitter_us = parse_number(&flag, &value)? as u64,
+                "--echo-drop-rate" => {
+                    options.echo_drop_rate = match value.parse::<f64>() {
+                        Ok(rate) if (0.0..=1.0).contains(&rate) => rate,
+                        _ => return Err(format!("{} expects a number between 0 and 1", flag)),
+                    }
+                }
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+        let payload_size = serialized_size(&payload(0, options.size)).unwrap() as usize;
+        if payload_size > MAX_RPMSG_BUFF_SIZE as usize {
+            return Err(format!(
+                "payloads of {} data bytes take {} bytes, an rpmsg buffer holds {}",
+                options.size, payload_size, MAX_RPMSG_BUFF_SIZE
+            ));
+        }
+        Ok(Invocation::Bench(options))
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    fn echo_config(&self) -> EchoConfig {
+        EchoConfig {
+            delay: Duration::from_micros(self.echo_delay_us),
+            jitter: Duration::from_micros(self.echo_jitter_us),
+            drop_rate: self.echo_drop_rate,
+            ..EchoConfig::default()
+        }
+    }
+}
+
+fn parse_number(flag: &str, value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got {}", flag, value))
+}
+
+fn payload(id: usize, size: usize) -> Payload {
+    Payload {
+        num: id,
+        data: vec![0; size],
+    }
+}
+
+/// the report of one run
+#[derive(Debug, Serialize)]
+struct BenchReport {
+    kernel: String,
+    options: BenchOptions,
+    summary: LatencySummary,
+}
+
+/// the endpoint created by prepare_environment, bound to the rpmsg_char_notify driver
+/// the prepared endpoint keeps the control interface open, it is closed after the endpoint
+struct NotifyEndpoint {
+    endpoint: RPMsgEndpoint,
+    _prepared: PreparedEndpoint,
+}
+
+impl AbstractRPMsgChannel for NotifyEndpoint {
+    fn instantiate(
+        channel_name: String,
+        virtio_id: String,
+        version_number: String,
+    ) -> Result<Self, ChannelError> {
+        let prepared = prepare_environment(&ChannelConfig {
+            virtio_id,
+            channel_name,
+            version: version_number,
+            ..ChannelConfig::default()
+        })?;
+        let endpoint = RPMsgEndpoint::new(prepared.endpoint_path().to_string_lossy().into_owned())?;
+        Ok(NotifyEndpoint {
+            endpoint,
+            _prepared: prepared,
+        })
+    }
+
+    fn send(&mut self, message: &[u8]) -> Result<(), ChannelError> {
+        self.endpoint.send(message)
+    }
+
+    fn read(&mut self, capacity: usize) -> Result<Vec<u8>, ChannelError> {
+        self.endpoint.read(capacity)
+    }
+
+    fn max_message_size(&self) -> usize {
+        self.endpoint.max_message_size()
+    }
+
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<usize, ChannelError> {
+        self.endpoint.read_into(buf)
+    }
+}
+
+impl AsRawFd for NotifyEndpoint {
+    fn as_raw_fd(&self) -> RawFd {
+        self.endpoint.as_raw_fd()
+    }
+}
+
+/// send the payloads one by one and wait for every reply before sending the next one
+fn run<C>(channel: C, options: &BenchOptions) -> Result<LatencyRecorder, ChannelError>
+where
+    C: AbstractRPMsgChannel + AsRawFd + Send + 'static,
+{
+    let channel = Arc::new(Mutex::new(channel));
+    let (tx, rx) = mpsc::channel::<(Instant, LeasedBuffer)>();
+    // why: the receive thread reads into pooled buffers, so it doesn't allocate per reply
+    let pool = BufferPool::new(REPLY_BUFFERS, MAX_RPMSG_BUFF_SIZE as usize);
+    let handle = options.strategy.strategy().start(
+        channel.clone(),
+        MessageSink::Leased(
+            pool,
+            Box::new(move |message| {
+                // why: take the time stamp as soon as the strategy hands the message over
+                let _ = tx.send((Instant::now(), message));
+            }),
+        ),
+    )?;
+
+    let mut warmup = LatencyRecorder::with_capacity(options.warmup);
+    let mut recorder = LatencyRecorder::with_capacity(options.count);
+    for id in 1..=options.warmup + options.count {
+        let message = serialize(&payload(id, options.size)).unwrap();
+        let send_time = Instant::now();
+        channel.lock().unwrap().send(&message)?;
+        if id <= options.warmup {
+            warmup.record_send(id, send_time);
+        } else {
+            recorder.record_send(id, send_time);
+        }
+        wait_reply(&rx, id, options, &mut warmup, &mut recorder);
+    }
+    // pick up the replies which came back late
+    while let Ok((receive_time, message)) = rx.recv_timeout(options.timeout()) {
+        record_reply(receive_time, &message, options, &mut warmup, &mut recorder);
+    }
+    handle.stop()?;
+    Ok(recorder)
+}
+
+// wait for the reply to id, replies to other ids received meanwhile are recorded too
+fn wait_reply(
+    rx: &Receiver<(Instant, LeasedBuffer)>,
+    id: usize,
+    options: &BenchOptions,
+    warmup: &mut LatencyRecorder,
+    recorder: &mut LatencyRecorder,
+) {
+    let deadline = Instant::now() + options.timeout();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(remaining) {
+            Ok((receive_time, message)) => {
+                if record_reply(receive_time, &message, options, warmup, recorder) == Some(id) {
+                    return;
+                }
+            }
+            Err(_) => {
+                eprintln!("no reply to payload {} within {:?}", id, options.timeout());
+                return;
+            }
+        }
+    }
+}
+
+fn record_reply(
+    receive_time: Instant,
+    message: &[u8],
+    options: &BenchOptions,
+    warmup: &mut LatencyRecorder,
+    recorder: &mut LatencyRecorder,
+) -> Option<usize> {
+    match deserialize::<Payload>(message) {
+        Ok(reply) if reply.num <= options.warmup => {
+            warmup.record_receive(reply.num, receive_time);
+            Some(reply.num)
+        }
+        Ok(reply) => {
+            recorder.record_receive(reply.num, receive_time);
+            Some(reply.num)
+        }
+        Err(e) => {
+            eprintln!("dropping a reply which is not a payload: {:?}", e);
+            None
+        }
+    }
+}
+
+/// run against the echo_test firmware on the remote processor
+fn run_remoteproc(options: &BenchOptions) -> Result<LatencyRecorder, ChannelError> {
+    let remote_proc = RemoteprocManager::new(&options.remoteproc).unwrap_or_else(|e| {
+        eprintln!("can't find {}: {}", options.remoteproc, e);
+        process::exit(1);
+    });
+    if let Err(e) = remote_proc.load_firmware_rs(options.firmware.clone()) {
+        eprintln!(
+            "can't load {} on {}: {}",
+            options.firmware, options.remoteproc, e
+        );
+        process::exit(1);
+    }
+    if let Err(e) = remote_proc.start().and_then(|_| {
+        remote_proc.wait_for_state(RemoteprocState::Running, REMOTEPROC_START_TIMEOUT)
+    }) {
+        eprintln!("can't start {}: {}", options.remoteproc, e);
+        process::exit(1);
+    }
+
+    let result = NotifyEndpoint::instantiate(
+        "rpmsg-openamp-demo-channel".to_string(),
+        "virtio0".to_string(),
+        "1.0".to_string(),
+    )
+    .and_then(|channel| run(channel, options));
+    if let Err(e) = remote_proc.stop() {
+        eprintln!("can't stop {}: {}", options.remoteproc, e);
+    }
+    result
+}
+
+/// run against an echo responder on the other half of a loopback channel
+fn run_loopback(options: &BenchOptions) -> Result<LatencyRecorder, ChannelError> {
+    let (host, remote) = LoopbackRPMsgChannel::named_pair("rpmsg-bench")?;
+    let echo = EchoResponder::new(remote, options.echo_config()).spawn();
+    let recorder = run(host, options)?;
+    let stats = echo.stop()?;
+    println!(
+        "echo: {} echoed, {} dropped, {} invalid",
+        stats.echoed, stats.dropped, stats.invalid
+    );
+    Ok(recorder)
+}
+
+fn export(recorder: &LatencyRecorder, report: &BenchReport, options: &BenchOptions) {
+    let path = match &options.output {
+        Some(path) => path,
+        None => return,
+    };
+    let file = File::create(path).unwrap_or_else(|e| {
+        eprintln!("can't create {:?}: {}", path, e);
+        process::exit(1);
+    });
+    let writer = BufWriter::new(file);
+    let result = match ExportFormat::from_path(path).unwrap() {
+        ExportFormat::Json => serde_json::to_writer_pretty(writer, report).map_err(Into::into),
+        format => recorder.export(format, writer),
+    };
+    if let Err(e) = result {
+        eprintln!("can't write {:?}: {}", path, e);
+        process::exit(1);
+    }
+}
+
+fn main() {
+    let options = match BenchOptions::parse(env::args().skip(1)) {
+        Ok(Invocation::Bench(options)) => options,
+        Ok(Invocation::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let result = if options.loopback {
+        run_loopback(&options)
+    } else {
+        run_remoteproc(&options)
+    };
+    let recorder = result.unwrap_or_else(|e| {
+        eprintln!("benchmark failed: {}", e);
+        process::exit(1);
+    });
+
+    let report = BenchReport {
+        kernel: uname().release().to_string(),
+        summary: recorder.summary(),
+        options: options.clone(),
+    };
+    println!(
+        "{} on {}: {} payloads of {} data bytes, {} warmup",
+        options.strategy, report.kernel, options.count, options.size, options.warmup
+    );
+    println!("{}", report.summary);
+    export(&recorder, &report, &options);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invocation(args: &[&str]) -> Result<Invocation, String> {
+        BenchOptions::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn parse(args: &[&str]) -> Result<BenchOptions, String> {
+        match invocation(args)? {
+            Invocation::Bench(options) => Ok(options),
+            Invocation::Help => Err("asked for the usage".to_string()),
+        }
+    }
+
+    #[test]
+    fn parse_flags() {
+        let options = parse(&[
+            "--strategy",
+            "sigio",
+            "--count",
+            "10",
+            "--remoteproc",
+            "remoteproc1",
+            "--output",
+            "run.json",
+        ])
+        .unwrap();
+        assert_eq!(options.strategy, NotificationKind::Sigio);
+        assert_eq!(options.count, 10);
+        assert_eq!(options.remoteproc, "remoteproc1");
+        assert_eq!(options.firmware, "echo_test.elf");
+        assert_eq!(options.output, Some(PathBuf::from("run.json")));
+    }
+
+    #[test]
+    fn reject_bad_flags() {
+        assert!(parse(&["--strategy", "select"]).is_err());
+        assert!(parse(&["--count"]).is_err());
+        assert!(parse(&["--output", "run.txt"]).is_err());
+        assert!(parse(&["--size", "1000"]).is_err());
+        assert!(parse(&["--echo-drop-rate", "2"]).is_err());
+    }
+
+    #[test]
+    fn help_stops_parsing() {
+        assert!(matches!(invocation(&["--help"]), Ok(Invocation::Help)));
+        assert!(matches!(
+            invocation(&["--count", "10", "-h", "--strategy", "select"]),
+            Ok(Invocation::Help)
+        ));
+        assert!(matches!(invocation(&[]), Ok(Invocation::Bench(_))));
+    }
+
+    #[test]
+    fn loopback_end_to_end() {
+        for strategy in ["epoll", "sigio", "blocking"] {
+            let options = parse(&[
+                "--loopback",
+                "--strategy",
+                strategy,
+                "--count",
+                "20",
+                "--warmup",
+                "2",
+                "--timeout",
+                "200",
+            ])
+            .unwrap();
+            let summary = run_loopback(&options).unwrap().summary();
+            assert_eq!(summary.sent, 20, "{}", strategy);
+            assert_eq!(summary.completed, 20, "{}", strategy);
+            assert!(summary.missing.is_empty());
+            assert!(summary.stats.is_some());
+        }
+    }
+
+    #[test]
+    fn loopback_reports_dropped_payloads() {
+        let options = parse(&[
+            "--loopback",
+            "--count",
+            "5",
+            "--warmup",
+            "0",
+            "--timeout",
+            "20",
+            "--echo-drop-rate",
+            "1",
+        ])
+        .unwrap();
+        let summary = run_loopback(&options).unwrap().summary();
+        assert_eq!(summary.missing, vec![1, 2, 3, 4, 5]);
+        assert_eq!(summary.stats, None);
+    }
+}
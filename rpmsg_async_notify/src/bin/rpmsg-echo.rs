@@ -0,0 +1,102 @@
+use rpmsg_async_notify::channel::{AbstractRPMsgChannel, OctRPMsgChannel};
+use rpmsg_async_notify::echo::{EchoConfig, EchoResponder};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::flag;
+use std::env;
+use std::process;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+const USAGE: &str = "usage: rpmsg-echo [options]
+    --channel <name>        rpmsg channel to serve (default rpmsg-openamp-demo-channel)
+    --virtio <id>           virtio device of the channel (default virtio0)
+    --version <n>           version of the channel (default 1.0)
+    --delay-us <us>         time spent before every echo (default 0)
+    --jitter-us <us>        up to this much is added to the delay at random (default 0)
+    --drop-rate <0..1>      share of the payloads which are not echoed (default 0)
+    --seed <n>              seed of the random numbers (default 0)";
+
+struct EchoOptions {
+    channel: String,
+    virtio: String,
+    version: String,
+    config: EchoConfig,
+}
+
+impl EchoOptions {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<EchoOptions, String> {
+        let mut options = EchoOptions {
+            channel: "rpmsg-openamp-demo-channel".to_string(),
+            virtio: "virtio0".to_string(),
+            version: "1.0".to_string(),
+            config: EchoConfig::default(),
+        };
+        while let Some(flag) = args.next() {
+            if flag == "--help" || flag == "-h" {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value of {}", flag))?;
+            match flag.as_str() {
+                "--channel" => options.channel = value,
+                "--virtio" => options.virtio = value,
+                "--version" => options.version = value,
+                "--delay-us" => options.config.delay = parse_micros(&flag, &value)?,
+                "--jitter-us" => options.config.jitter = parse_micros(&flag, &value)?,
+                "--drop-rate" => options.config.drop_rate = parse_rate(&flag, &value)?,
+                "--seed" => {
+                    options.config.seed = value
+                        .parse()
+                        .map_err(|_| format!("{} expects a number, got {}", flag, value))?
+                }
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn parse_micros(flag: &str, value: &str) -> Result<Duration, String> {
+    value
+        .parse()
+        .map(Duration::from_micros)
+        .map_err(|_| format!("{} expects a number of microseconds, got {}", flag, value))
+}
+
+fn parse_rate(flag: &str, value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if (0.0..=1.0).contains(&rate) => Ok(rate),
+        _ => Err(format!(
+            "{} expects a number between 0 and 1, got {}",
+            flag, value
+        )),
+    }
+}
+
+fn main() {
+    let options = EchoOptions::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    });
+
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        flag::register(signal, stop.clone()).expect("can't register signal handler");
+    }
+
+    let channel = OctRPMsgChannel::instantiate(options.channel, options.virtio, options.version)
+        .unwrap_or_else(|e| {
+            eprintln!("can't open the channel: {}", e);
+            process::exit(1);
+        });
+    let mut responder = EchoResponder::new(channel, options.config);
+    let result = responder.run(&stop);
+    println!("{:?}", responder.stats());
+    if let Err(e) = result {
+        eprintln!("echo responder failed: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,316 @@
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use rpmsg_async_notify::capture::{CaptureReader, CaptureRecord, Direction};
+use rpmsg_async_notify::channel::{AbstractRPMsgChannel, ChannelError, OctRPMsgChannel};
+use rpmsg_async_notify::echo::{EchoConfig, EchoResponder};
+use rpmsg_async_notify::loopback::LoopbackRPMsgChannel;
+use rpmsg_async_notify::MAX_RPMSG_BUFF_SIZE;
+use std::env;
+use std::os::unix::prelude::AsRawFd;
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: rpmsg-replay print <capture>
+       rpmsg-replay replay <capture> [options]
+    --endpoint <name>       replay only the messages sent through this endpoint (default all)
+    --channel <name>        rpmsg channel to replay on (default rpmsg-openamp-demo-channel)
+    --virtio <id>           virtio device of the channel (default virtio0)
+    --version <n>           version of the channel (default 1.0)
+    --no-timing             send back to back instead of keeping the captured intervals
+    --wait-ms <ms>          how long to wait for replies after the last message (default 100)
+    --loopback              replay against an in-process echo, no remote processor needed";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Print,
+    Replay,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ReplayOptions {
+    command: Command,
+    capture: PathBuf,
+    endpoint: Option<String>,
+    channel: String,
+    virtio: String,
+    version: String,
+    timing: bool,
+    wait: Duration,
+    loopback: bool,
+}
+
+impl ReplayOptions {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<ReplayOptions, String> {
+        let command = match args.next().as_deref() {
+            Some("print") => Command::Print,
+            Some("replay") => Command::Replay,
+            Some("--help") | Some("-h") => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            Some(command) => return Err(format!("unknown command {}", command)),
+            None => return Err("missing command".to_string()),
+        };
+        let capture = args
+            .next()
+            .map(PathBuf::from)
+            .ok_or_else(|| "missing capture file".to_string())?;
+        let mut options = ReplayOptions {
+            command,
+            capture,
+            endpoint: None,
+            channel: "rpmsg-openamp-demo-channel".to_string(),
+            virtio: "virtio0".to_string(),
+            version: "1.0".to_string(),
+            timing: true,
+            wait: Duration::from_millis(100),
+            loopback: false,
+        };
+        while let Some(flag) = args.next() {
+            match flag.as_str() {
+                "--no-timing" => {
+                    options.timing = false;
+                    continue;
+                }
+                "--loopback" => {
+                    options.loopback = true;
+                    continue;
+                }
+                _ => {}
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value of {}", flag))?;
+            match flag.as_str() {
+                "--endpoint" => options.endpoint = Some(value),
+                "--channel" => options.channel = value,
+                "--virtio" => options.virtio = value,
+                "--version" => options.version = value,
+                "--wait-ms" => {
+                    options.wait = value
+                        .parse()
+                        .map(Duration::from_millis)
+                        .map_err(|_| format!("{} expects a number, got {}", flag, value))?
+                }
+                _ => return Err(format!("unknown option {}", flag)),
+            }
+        }
+        Ok(options)
+    }
+
+    fn replays(&self, record: &CaptureRecord) -> bool {
+        record.direction == Direction::Sent
+            && self
+                .endpoint
+                .as_ref()
+                .is_none_or(|endpoint| *endpoint == record.endpoint)
+    }
+}
+
+/// what a replay did
+#[derive(Debug, Default, PartialEq)]
+struct ReplayStats {
+    sent: usize,
+    received: usize,
+}
+
+fn print_message(timestamp: Duration, direction: Direction, endpoint: &str, message: &[u8]) {
+    let hex: Vec<String> = message.iter().map(|byte| format!("{:02x}", byte)).collect();
+    println!(
+        "{:>14.6} {} {} {:>4} {}",
+        timestamp.as_secs_f64(),
+        direction,
+        endpoint,
+        message.len(),
+        hex.join(" ")
+    );
+}
+
+fn print_capture(options: &ReplayOptions) -> Result<(), ChannelError> {
+    for record in CaptureReader::open(&options.capture)? {
+        let record = record?;
+        print_message(
+            record.timestamp,
+            record.direction,
+            &record.endpoint,
+            &record.message,
+        );
+    }
+    Ok(())
+}
+
+/// print every reply which arrives before deadline
+fn receive_until<C>(
+    channel: &mut C,
+    name: &str,
+    start: Instant,
+    deadline: Instant,
+) -> Result<usize, ChannelError>
+where
+    C: AbstractRPMsgChannel + AsRawFd,
+{
+    let mut received = 0;
+    loop {
+        loop {
+            match channel.read(MAX_RPMSG_BUFF_SIZE as usize) {
+                Ok(message) if message.is_empty() => return Ok(received),
+                Ok(message) => {
+                    print_message(start.elapsed(), Direction::Received, name, &message);
+                    received += 1;
+                }
+                Err(ChannelError::WouldBlock { .. }) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(received);
+        }
+        let mut fds = [PollFd::new(channel.as_raw_fd(), PollFlags::POLLIN)];
+        let timeout_ms = remaining.as_millis().clamp(1, i32::MAX as u128) as i32;
+        match poll(&mut fds, timeout_ms) {
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// send the host side of the capture through channel, printing the replies as they arrive
+fn replay<C>(channel: &mut C, options: &ReplayOptions) -> Result<ReplayStats, ChannelError>
+where
+    C: AbstractRPMsgChannel + AsRawFd,
+{
+    let mut stats = ReplayStats::default();
+    let start = Instant::now();
+    let mut first_timestamp = None;
+    for record in CaptureReader::open(&options.capture)? {
+        let record = record?;
+        if !options.replays(&record) {
+            continue;
+        }
+        if options.timing {
+            let first = *first_timestamp.get_or_insert(record.timestamp);
+            let due = start + record.timestamp.saturating_sub(first);
+            stats.received += receive_until(channel, &options.channel, start, due)?;
+        }
+        channel.send(&record.message)?;
+        print_message(
+            start.elapsed(),
+            Direction::Sent,
+            &options.channel,
+            &record.message,
+        );
+        stats.sent += 1;
+    }
+    let deadline = Instant::now() + options.wait;
+    stats.received += receive_until(channel, &options.channel, start, deadline)?;
+    Ok(stats)
+}
+
+fn run(options: &ReplayOptions) -> Result<(), ChannelError> {
+    if options.command == Command::Print {
+        return print_capture(options);
+    }
+    let stats = if options.loopback {
+        let (mut host, remote) = LoopbackRPMsgChannel::named_pair("rpmsg-replay")?;
+        let echo = EchoResponder::new(remote, EchoConfig::default()).spawn();
+        let stats = replay(&mut host, options)?;
+        echo.stop()?;
+        stats
+    } else {
+        let mut channel = OctRPMsgChannel::instantiate(
+            options.channel.clone(),
+            options.virtio.clone(),
+            options.version.clone(),
+        )?;
+        replay(&mut channel, options)?
+    };
+    println!(
+        "replayed {} messages, received {} replies",
+        stats.sent, stats.received
+    );
+    Ok(())
+}
+
+fn main() {
+    let options = ReplayOptions::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    });
+    if let Err(e) = run(&options) {
+        eprintln!("rpmsg-replay failed: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode::serialize;
+    use rpmsg_async_notify::capture::CaptureWriter;
+    use rpmsg_async_notify::Payload;
+    use std::fs::File;
+
+    fn parse(args: &[&str]) -> Result<ReplayOptions, String> {
+        ReplayOptions::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parse_commands() {
+        let options = parse(&[
+            "replay",
+            "link.rpmsgcap",
+            "--endpoint",
+            "rpmsg0",
+            "--no-timing",
+            "--wait-ms",
+            "5",
+        ])
+        .unwrap();
+        assert_eq!(options.command, Command::Replay);
+        assert_eq!(options.capture, PathBuf::from("link.rpmsgcap"));
+        assert_eq!(options.endpoint.as_deref(), Some("rpmsg0"));
+        assert!(!options.timing);
+        assert_eq!(options.wait, Duration::from_millis(5));
+        assert!(parse(&["print"]).is_err());
+        assert!(parse(&["dump", "link.rpmsgcap"]).is_err());
+        assert!(parse(&["replay", "link.rpmsgcap", "--wait-ms", "soon"]).is_err());
+    }
+
+    #[test]
+    fn replay_host_side_against_loopback() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("link.rpmsgcap");
+        let mut writer = CaptureWriter::new(File::create(&path).unwrap()).unwrap();
+        for (id, direction, endpoint) in [
+            (0, Direction::Sent, "rpmsg0"),
+            (0, Direction::Received, "rpmsg0"),
+            (1, Direction::Sent, "rpmsg1"),
+            (2, Direction::Sent, "rpmsg0"),
+        ] {
+            let message = serialize(&Payload::new(id)).unwrap();
+            let timestamp = Duration::from_millis(id as u64);
+            writer
+                .write_record(direction, endpoint, timestamp, &message)
+                .unwrap();
+        }
+        drop(writer);
+
+        let mut options = parse(&["replay", path.to_str().unwrap(), "--wait-ms", "500"]).unwrap();
+        let (mut host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let echo = EchoResponder::new(remote, EchoConfig::default()).spawn();
+        let stats = replay(&mut host, &options).unwrap();
+        assert_eq!(
+            stats,
+            ReplayStats {
+                sent: 3,
+                received: 3
+            }
+        );
+
+        options.endpoint = Some("rpmsg0".to_string());
+        assert_eq!(replay(&mut host, &options).unwrap().sent, 2);
+        assert_eq!(echo.stop().unwrap().echoed, 5);
+    }
+}
@@ -0,0 +1,111 @@
+use rpmsg_async_notify::firmware::{sha256_hex, FirmwareImage};
+use rpmsg_async_notify::resource_table::ResourceTable;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str = "usage: rproc-inspect <firmware.elf>...
+    print the resource table of R5 firmware ELFs and warn about what keeps
+    rpmsg from working with them";
+
+/// what the command line asks for
+#[derive(Debug, PartialEq)]
+enum Invocation {
+    Inspect(Vec<PathBuf>),
+    Help,
+}
+
+fn parse(args: impl Iterator<Item = String>) -> Result<Invocation, String> {
+    let mut firmwares = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(Invocation::Help),
+            option if option.starts_with('-') => return Err(format!("unknown option {}", option)),
+            _ => firmwares.push(PathBuf::from(arg)),
+        }
+    }
+    if firmwares.is_empty() {
+        return Err("missing firmware".to_string());
+    }
+    Ok(Invocation::Inspect(firmwares))
+}
+
+/// the report of the firmware in image, and whether rpmsg will work with it
+fn inspect(image: &[u8]) -> Result<(String, Vec<String>), String> {
+    let firmware = FirmwareImage::parse(image).map_err(|e| e.to_string())?;
+    let mut warnings = Vec::new();
+    if let Err(e) = firmware.validate() {
+        warnings.push(format!("remoteproc won't boot it, {}", e));
+    }
+    let table = ResourceTable::from_firmware(&firmware).map_err(|e| e.to_string())?;
+    warnings.extend(
+        table
+            .rpmsg_warnings()
+            .iter()
+            .map(|warning| warning.to_string()),
+    );
+    let report = format!("sha256 {}\n{}", sha256_hex(image), table);
+    Ok((report, warnings))
+}
+
+fn main() {
+    let firmwares = match parse(env::args().skip(1)) {
+        Ok(Invocation::Inspect(firmwares)) => firmwares,
+        Ok(Invocation::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    let mut failed = false;
+    for path in firmwares {
+        println!("{}", path.display());
+        match fs::read(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|image| inspect(&image))
+        {
+            Ok((report, warnings)) => {
+                print!("{}", report);
+                for warning in warnings {
+                    println!("warning: {}", warning);
+                }
+            }
+            Err(e) => {
+                eprintln!("rproc-inspect failed on {}: {}", path.display(), e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_arguments() {
+        let args = |args: &[&str]| parse(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(
+            args(&["echo_test.elf", "rpc.elf"]),
+            Ok(Invocation::Inspect(vec![
+                PathBuf::from("echo_test.elf"),
+                PathBuf::from("rpc.elf")
+            ]))
+        );
+        assert_eq!(args(&["--help"]), Ok(Invocation::Help));
+        assert_eq!(
+            args(&["echo_test.elf", "-h", "--verbose"]),
+            Ok(Invocation::Help)
+        );
+        assert!(args(&[]).is_err());
+        assert!(args(&["--verbose", "echo_test.elf"]).is_err());
+        assert!(inspect(b"#!/bin/sh\n").is_err());
+    }
+}
@@ -0,0 +1,553 @@
+use crate::channel::{AbstractRPMsgChannel, ChannelError};
+use crate::time_utils::monotonic_time;
+use log::warn;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::prelude::{AsRawFd, RawFd};
+use std::path::{self, Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+/// every capture file starts with the magic and the format version
+pub const CAPTURE_MAGIC: &[u8; 8] = b"RPMSGCAP";
+pub const CAPTURE_VERSION: u16 = 1;
+/// instantiate of CapturedChannel writes to the capture file named by this variable
+pub const CAPTURE_PATH_ENV: &str = "RPMSG_CAPTURE";
+
+// the writer shared by the clones of a Capture
+type LockedWriter = Mutex<CaptureWriter<Box<dyn Write + Send>>>;
+
+lazy_static! {
+    // the captures opened by Capture::open_shared, keyed by absolute path
+    // an entry outlives its capture, so a later channel appends instead of truncating
+    static ref SHARED_CAPTURES: Mutex<HashMap<PathBuf, Weak<LockedWriter>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// which way a captured message went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// sent by the host to the remote processor
+    Sent,
+    /// received by the host from the remote processor
+    Received,
+}
+
+impl Direction {
+    fn to_byte(self) -> u8 {
+        match self {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Direction> {
+        match byte {
+            0 => Some(Direction::Sent),
+            1 => Some(Direction::Received),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Direction::Sent => write!(f, "->"),
+            Direction::Received => write!(f, "<-"),
+        }
+    }
+}
+
+/// one message of a capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    pub direction: Direction,
+    /// the name of the endpoint the message went through
+    pub endpoint: String,
+    /// CLOCK_MONOTONIC when the message was sent or received
+    pub timestamp: Duration,
+    pub message: Vec<u8>,
+}
+
+fn io_error(error: io::Error) -> ChannelError {
+    ChannelError::IOError {
+        error: error.to_string(),
+    }
+}
+
+fn invalid_capture(reason: &str) -> ChannelError {
+    ChannelError::InvalidCapture {
+        reason: reason.to_string(),
+    }
+}
+
+/// # Capture writer
+/// Writes the capture format, all integers are little endian:
+/// - header: the magic `RPMSGCAP`, u16 format version
+/// - every record: u8 direction (0 sent, 1 received), u64 CLOCK_MONOTONIC nanoseconds,
+///   u8 endpoint name length, the name, u32 message length, the message
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    /// write the header, records follow it
+    pub fn new(mut writer: W) -> Result<Self, ChannelError> {
+        writer.write_all(CAPTURE_MAGIC).map_err(io_error)?;
+        writer
+            .write_all(&CAPTURE_VERSION.to_le_bytes())
+            .map_err(io_error)?;
+        Ok(CaptureWriter { writer })
+    }
+
+    /// write records after those already written to writer, which has the header
+    pub fn append(writer: W) -> Self {
+        CaptureWriter { writer }
+    }
+
+    pub fn write_record(
+        &mut self,
+        direction: Direction,
+        endpoint: &str,
+        timestamp: Duration,
+        message: &[u8],
+    ) -> Result<(), ChannelError> {
+        let endpoint_len: u8 = endpoint
+            .len()
+            .try_into()
+            .map_err(|_| invalid_capture("endpoint name longer than 255 bytes"))?;
+        let message_len: u32 = message
+            .len()
+            .try_into()
+            .map_err(|_| invalid_capture("message larger than 4 GiB"))?;
+        let timestamp = timestamp.as_nanos() as u64;
+        let mut write = || -> io::Result<()> {
+            self.writer.write_all(&[direction.to_byte()])?;
+            self.writer.write_all(&timestamp.to_le_bytes())?;
+            self.writer.write_all(&[endpoint_len])?;
+            self.writer.write_all(endpoint.as_bytes())?;
+            self.writer.write_all(&message_len.to_le_bytes())?;
+            self.writer.write_all(message)
+        };
+        write().map_err(io_error)
+    }
+
+    pub fn flush(&mut self) -> Result<(), ChannelError> {
+        self.writer.flush().map_err(io_error)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// # Capture reader
+/// Iterates over the records of a capture, a truncated record ends the iteration with an error.
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    // set after an error, so a broken capture isn't read further
+    failed: bool,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ChannelError> {
+        let file = File::open(path.as_ref()).map_err(|e| ChannelError::IOError {
+            error: format!("can't open {}: {}", path.as_ref().display(), e),
+        })?;
+        CaptureReader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// read and check the header
+    pub fn new(mut reader: R) -> Result<Self, ChannelError> {
+        let mut magic = [0u8; 8];
+        let mut version = [0u8; 2];
+        reader
+            .read_exact(&mut magic)
+            .and_then(|_| reader.read_exact(&mut version))
+            .map_err(|_| invalid_capture("missing header"))?;
+        if &magic != CAPTURE_MAGIC {
+            return Err(invalid_capture("not a capture file"));
+        }
+        let version = u16::from_le_bytes(version);
+        if version != CAPTURE_VERSION {
+            return Err(ChannelError::InvalidCapture {
+                reason: format!("unsupported version {}", version),
+            });
+        }
+        Ok(CaptureReader {
+            reader,
+            failed: false,
+        })
+    }
+
+    // None at the end of the capture, which has to be on a record boundary
+    fn read_record(&mut self) -> Result<Option<CaptureRecord>, ChannelError> {
+        let mut direction = [0u8; 1];
+        loop {
+            match self.reader.read(&mut direction) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(io_error(e)),
+            }
+        }
+        let direction =
+            Direction::from_byte(direction[0]).ok_or_else(|| invalid_capture("bad direction"))?;
+        let truncated = |_| invalid_capture("truncated record");
+        let mut timestamp = [0u8; 8];
+        self.reader.read_exact(&mut timestamp).map_err(truncated)?;
+        let mut endpoint_len = [0u8; 1];
+        self.reader
+            .read_exact(&mut endpoint_len)
+            .map_err(truncated)?;
+        let mut endpoint = vec![0u8; endpoint_len[0] as usize];
+        self.reader.read_exact(&mut endpoint).map_err(truncated)?;
+        let endpoint = String::from_utf8(endpoint)
+            .map_err(|_| invalid_capture("endpoint name is not utf-8"))?;
+        let mut message_len = [0u8; 4];
+        self.reader
+            .read_exact(&mut message_len)
+            .map_err(truncated)?;
+        // why: read through take, so a corrupted length can't allocate gigabytes up front
+        let message_len = u32::from_le_bytes(message_len) as u64;
+        let mut message = Vec::new();
+        (&mut self.reader)
+            .take(message_len)
+            .read_to_end(&mut message)
+            .map_err(io_error)?;
+        if message.len() as u64 != message_len {
+            return Err(invalid_capture("truncated record"));
+        }
+        Ok(Some(CaptureRecord {
+            direction,
+            endpoint,
+            timestamp: Duration::from_nanos(u64::from_le_bytes(timestamp)),
+            message,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord, ChannelError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let record = self.read_record().transpose();
+        self.failed = matches!(record, Some(Err(_)));
+        record
+    }
+}
+
+/// a capture shared by the channels writing to it, clones write to the same file
+/// buffered data is flushed by flush, after every record when flush_each_record is set,
+/// or when the last clone is dropped
+#[derive(Clone)]
+pub struct Capture {
+    writer: Arc<LockedWriter>,
+    flush_each_record: bool,
+}
+
+impl Capture {
+    /// start a capture on writer, records are flushed when the writer decides
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Result<Self, ChannelError> {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        Ok(Capture {
+            writer: Arc::new(Mutex::new(CaptureWriter::new(writer)?)),
+            flush_each_record: false,
+        })
+    }
+
+    /// create or truncate the capture file at path
+    /// every record is flushed to the file, so a crash of the process loses none
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, ChannelError> {
+        let file = File::create(path.as_ref()).map_err(|e| ChannelError::IOError {
+            error: format!("can't create {}: {}", path.as_ref().display(), e),
+        })?;
+        let mut capture = Capture::new(BufWriter::new(file))?;
+        capture.set_flush_each_record(true);
+        Ok(capture)
+    }
+
+    /// flush after every record, or leave it to flush and the drop of the last clone
+    pub fn set_flush_each_record(&mut self, enabled: bool) {
+        self.flush_each_record = enabled;
+    }
+
+    /// the capture of the file at path shared by every channel of the process
+    /// the first call creates or truncates the file, the next ones write to the same capture,
+    /// or append to the file once the earlier channels were dropped.
+    /// Every record is flushed to the file like with create.
+    pub fn open_shared<P: AsRef<Path>>(path: P) -> Result<Self, ChannelError> {
+        let path = path::absolute(path.as_ref()).map_err(|e| ChannelError::IOError {
+            error: format!("can't resolve {}: {}", path.as_ref().display(), e),
+        })?;
+        let mut captures = SHARED_CAPTURES.lock().unwrap();
+        let capture = match captures.get(&path) {
+            Some(writer) => match writer.upgrade() {
+                Some(writer) => {
+                    return Ok(Capture {
+                        writer,
+                        flush_each_record: true,
+                    })
+                }
+                None => {
+                    let file = OpenOptions::new().append(true).open(&path).map_err(|e| {
+                        ChannelError::IOError {
+                            error: format!("can't append to {}: {}", path.display(), e),
+                        }
+                    })?;
+                    let writer: Box<dyn Write + Send> = Box::new(BufWriter::new(file));
+                    Capture {
+                        writer: Arc::new(Mutex::new(CaptureWriter::append(writer))),
+                        flush_each_record: true,
+                    }
+                }
+            },
+            None => Capture::create(&path)?,
+        };
+        captures.insert(path, Arc::downgrade(&capture.writer));
+        Ok(capture)
+    }
+
+    pub fn record(
+        &self,
+        direction: Direction,
+        endpoint: &str,
+        message: &[u8],
+    ) -> Result<(), ChannelError> {
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_record(direction, endpoint, monotonic_time(), message)?;
+        if self.flush_each_record {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<(), ChannelError> {
+        self.writer.lock().unwrap().flush()
+    }
+}
+
+/// # Captured channel
+/// Wraps a channel and writes every message it sends or receives to a capture.
+/// A failing capture is logged and doesn't affect the messages.
+pub struct CapturedChannel<C: AbstractRPMsgChannel> {
+    channel: C,
+    endpoint: String,
+    capture: Capture,
+}
+
+impl<C: AbstractRPMsgChannel> CapturedChannel<C> {
+    /// capture the messages of channel, endpoint names the channel in the records
+    pub fn new(channel: C, endpoint: &str, capture: Capture) -> Self {
+        CapturedChannel {
+            channel,
+            endpoint: endpoint.to_string(),
+            capture,
+        }
+    }
+
+    pub fn get_ref(&self) -> &C {
+        &self.channel
+    }
+
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.channel
+    }
+
+    pub fn capture(&self) -> &Capture {
+        &self.capture
+    }
+
+    pub fn into_inner(self) -> C {
+        self.channel
+    }
+
+    /// instantiate like instantiate, capturing to the file at path
+    pub fn instantiate_to<P: AsRef<Path>>(
+        path: P,
+        channel_name: String,
+        virtio_id: String,
+        version_number: String,
+    ) -> Result<Self, ChannelError> {
+        let capture = Capture::open_shared(path)?;
+        let endpoint = channel_name.clone();
+        let channel = C::instantiate(channel_name, virtio_id, version_number)?;
+        Ok(CapturedChannel::new(channel, &endpoint, capture))
+    }
+
+    fn record(&self, direction: Direction, message: &[u8]) {
+        if let Err(e) = self.capture.record(direction, &self.endpoint, message) {
+            warn!("failed to capture a message of {}: {}", self.endpoint, e);
+        }
+    }
+}
+
+impl<C: AbstractRPMsgChannel> AbstractRPMsgChannel for CapturedChannel<C> {
+    /// instantiate the channel and capture to the file named by RPMSG_CAPTURE,
+    /// the channels instantiated with the same file share its capture
+    fn instantiate(
+        channel_name: String,
+        virtio_id: String,
+        version_number: String,
+    ) -> Result<Self, ChannelError> {
+        let path = env::var(CAPTURE_PATH_ENV).map_err(|_| ChannelError::InvalidCapture {
+            reason: format!("{} is not set", CAPTURE_PATH_ENV),
+        })?;
+        CapturedChannel::instantiate_to(path, channel_name, virtio_id, version_number)
+    }
+
+    fn send(&mut self, message: &[u8]) -> Result<(), ChannelError> {
+        self.channel.send(message)?;
+        self.record(Direction::Sent, message);
+        Ok(())
+    }
+
+    fn max_message_size(&self) -> usize {
+        self.channel.max_message_size()
+    }
+
+    fn read(&mut self, capacity: usize) -> Result<Vec<u8>, ChannelError> {
+        let message = self.channel.read(capacity)?;
+        if !message.is_empty() {
+            self.record(Direction::Received, &message);
+        }
+        Ok(message)
+    }
+
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<usize, ChannelError> {
+        let size = self.channel.read_into(buf)?;
+        if size > 0 {
+            self.record(Direction::Received, &buf[..size]);
+        }
+        Ok(size)
+    }
+}
+
+impl<C: AbstractRPMsgChannel + AsRawFd> AsRawFd for CapturedChannel<C> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.channel.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::LoopbackRPMsgChannel;
+    use crate::MAX_RPMSG_BUFF_SIZE;
+
+    #[test]
+    fn capture_and_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("link.rpmsgcap");
+        let (host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut host = CapturedChannel::new(host, "rpmsg0", Capture::create(&path).unwrap());
+        host.send(b"request").unwrap();
+        assert_eq!(
+            remote.read(MAX_RPMSG_BUFF_SIZE as usize).unwrap(),
+            b"request"
+        );
+        remote.send(b"reply").unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(host.read_into(&mut buf).unwrap(), 5);
+        assert!(host.read(MAX_RPMSG_BUFF_SIZE as usize).is_err());
+
+        // why: the records are on file while the capture is still open
+        let records: Vec<CaptureRecord> = CaptureReader::open(&path)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            (records[0].direction, records[0].endpoint.as_str()),
+            (Direction::Sent, "rpmsg0")
+        );
+        assert_eq!(records[0].message, b"request");
+        assert_eq!(records[1].direction, Direction::Received);
+        assert_eq!(records[1].message, b"reply");
+        assert!(records[0].timestamp <= records[1].timestamp);
+    }
+
+    #[test]
+    fn channels_share_the_capture_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shared.rpmsgcap");
+        let instantiate = |channel_name: &str| {
+            CapturedChannel::<LoopbackRPMsgChannel>::instantiate_to(
+                &path,
+                channel_name.to_string(),
+                "virtio0".to_string(),
+                "1.0".to_string(),
+            )
+            .unwrap()
+        };
+        let mut first = instantiate("capture-first");
+        let mut second = instantiate("capture-second");
+        first.send(b"one").unwrap();
+        second.send(b"two").unwrap();
+        first.send(b"three").unwrap();
+        drop(first);
+        drop(second);
+        // why: the earlier channels are gone, the file is appended to, not truncated
+        let mut third = instantiate("capture-third");
+        third.send(b"four").unwrap();
+        drop(third);
+
+        let records: Vec<(String, Vec<u8>)> = CaptureReader::open(&path)
+            .unwrap()
+            .map(|record| record.map(|record| (record.endpoint, record.message)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            records,
+            vec![
+                ("capture-first".to_string(), b"one".to_vec()),
+                ("capture-second".to_string(), b"two".to_vec()),
+                ("capture-first".to_string(), b"three".to_vec()),
+                ("capture-third".to_string(), b"four".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_broken_captures() {
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        writer
+            .write_record(
+                Direction::Received,
+                "rpmsg1",
+                Duration::from_nanos(42),
+                b"hi",
+            )
+            .unwrap();
+        let bytes = writer.into_inner();
+
+        let mut reader = CaptureReader::new(&bytes[..]).unwrap();
+        assert_eq!(
+            reader.next(),
+            Some(Ok(CaptureRecord {
+                direction: Direction::Received,
+                endpoint: "rpmsg1".to_string(),
+                timestamp: Duration::from_nanos(42),
+                message: b"hi".to_vec(),
+            }))
+        );
+        assert_eq!(reader.next(), None);
+
+        let mut reader = CaptureReader::new(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(
+            reader.next(),
+            Some(Err(invalid_capture("truncated record")))
+        );
+        assert_eq!(reader.next(), None);
+        assert!(CaptureReader::new(&b"PCAPNG\0\0\x01\0"[..]).is_err());
+    }
+}
@@ -1,18 +1,38 @@
-use log::{error, trace, warn};
+use crate::checksum::{self, CHECKSUM_LEN};
+use crate::device::RPMsgDeviceName;
+use crate::stats::{EndpointCounters, EndpointStats};
+use crate::sysfs::SysfsRoot;
+use crate::uevent::UeventSocket;
+use crate::MAX_RPMSG_BUFF_SIZE;
+use log::{error, info, trace, warn};
 use nix::fcntl::{open, OFlag};
-use nix::ioctl_write_buf;
 use nix::libc::{__u32, c_char};
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
 use nix::sys::stat::Mode;
+use nix::sys::uio::{readv, IoVec};
 use nix::unistd::{access, close, read, write, AccessFlags};
+use nix::{ioctl_none, ioctl_write_buf};
 use snafu::ResultExt;
 use snafu::Snafu;
 use std::fs::File;
 use std::fs::{self, OpenOptions};
 use std::io::{self, prelude::*};
-use std::os::unix::prelude::RawFd;
-use std::path::Path;
+use std::os::unix::prelude::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 const RPMSG_ADDR_ANY: __u32 = 0xffffffff;
+/// the size of the name of an endpoint in the kernel, NUL terminator included
+pub const RPMSG_NAME_SIZE: usize = 32;
+/// the upstream rpmsg character driver
+pub const RPMSG_CHRDEV_DRIVER: &str = "rpmsg_chrdev";
+/// the rpmsg character driver patched to notify the application of new messages
+pub const RPMSG_CHAR_NOTIFY_DRIVER: &str = "rpmsg_char_notify";
+/// how long to wait for the remote to announce its rpmsg device after it was started
+pub const DEFAULT_DEVICE_TIMEOUT: Duration = Duration::from_secs(10);
+/// how often the device is checked when neither uevents nor inotify can report it
+const DEVICE_RECHECK_INTERVAL: Duration = Duration::from_millis(10);
 #[derive(Snafu, Debug, Clone, PartialEq)]
 #[snafu(visibility = "pub")]
 pub enum SystemFileHandlerError {
@@ -75,6 +95,94 @@ pub enum ChannelError {
     /// failed to convert from u8 to i8
     #[snafu(display("can't convert a u8 to i8"))]
     FailedToConvertU8ToI8 { num: u8 },
+    /// the kernel refused to destroy the endpoint
+    #[snafu(display("can't destroy endpoint {}, error: {}", endpoint_name, source))]
+    FailedToDestroyEndpoint {
+        endpoint_name: String,
+        source: nix::Error,
+    },
+    /// can't close the file specified by path
+    #[snafu(display("can't close {}, error: {}", path, source))]
+    FailedToCloseFileError { path: String, source: nix::Error },
+    /// the message is larger than the channel accepts, nothing was sent
+    #[snafu(display(
+        "message of {} bytes exceeds the limit of {} bytes",
+        message_size,
+        max_size
+    ))]
+    MessageTooLarge {
+        message_size: usize,
+        max_size: usize,
+    },
+    /// can't serialize the message, a wrapper for bincode error because it is not clonable
+    #[snafu(display("failed to serialize message, error {}", error))]
+    FailedToSerialize { error: String },
+    /// can't decode the received bytes into the expected message, the bytes are kept for inspection
+    #[snafu(display("failed to decode {} bytes, error {}", raw.len(), error))]
+    FailedToDecode { error: String, raw: Vec<u8> },
+    /// the received bytes are not a valid fragment
+    #[snafu(display("invalid fragment, {}", reason))]
+    InvalidFragment { reason: String },
+    /// fragments carry 1 to 65535 bytes of the message
+    #[snafu(display("fragment payload size {} is not in 1..=65535", size))]
+    InvalidFragmentPayloadSize { size: usize },
+    /// a fragment of the message was received twice, the copy is dropped
+    #[snafu(display("fragment {} of message {} received twice", fragment_index, message_id))]
+    DuplicatedFragment {
+        message_id: u16,
+        fragment_index: u16,
+    },
+    /// the message was not completed in time, the received fragments are dropped
+    #[snafu(display("message {} timed out, missing fragments {:?}", message_id, missing))]
+    IncompleteMessage { message_id: u16, missing: Vec<u16> },
+    /// the remote didn't answer the request in time
+    #[snafu(display("no response to request {} within {:?}", seq, timeout))]
+    RpcTimeout { seq: u32, timeout: Duration },
+    /// the remote didn't announce the rpmsg device in time
+    #[snafu(display("rpmsg device {} didn't show up within {:?}", device_name, timeout))]
+    DeviceTimeout {
+        device_name: String,
+        timeout: Duration,
+    },
+    /// the name doesn't follow `{virtio_id}.{channel_name}.{src}.{dst}`
+    #[snafu(display("{} is not an rpmsg device name", name))]
+    InvalidDeviceName { name: String },
+    /// two endpoints of a set have the same name
+    #[snafu(display("endpoint {} configured twice", endpoint_name))]
+    DuplicatedEndpoint { endpoint_name: String },
+    /// the kernel keeps endpoint names in RPMSG_NAME_SIZE bytes, NUL terminator included
+    #[snafu(display("endpoint name {} is longer than {} bytes", name, max_len))]
+    EndpointNameTooLong { name: String, max_len: usize },
+    /// the rpmsg device is already instantiated and still in use
+    #[snafu(display("rpmsg device {} is already in use", device_name))]
+    DeviceInUse { device_name: String },
+    /// every buffer of the pool is leased, the message was left pending
+    #[snafu(display("all {} buffers of the pool are in use", capacity))]
+    BufferPoolExhausted { capacity: usize },
+    /// a capture file which can't be read or a record which can't be written
+    #[snafu(display("invalid capture, {}", reason))]
+    InvalidCapture { reason: String },
+    /// the checksum at the end of a message doesn't match the message
+    #[snafu(display(
+        "checksum mismatch on {}, received {:#010x}, computed {:#010x}",
+        endpoint_name,
+        received,
+        computed
+    ))]
+    ChecksumMismatch {
+        endpoint_name: String,
+        received: u32,
+        computed: u32,
+    },
+    /// nothing to read, or no room to send, on a non-blocking endpoint
+    #[snafu(display("endpoint {} is not ready, try again", endpoint_name))]
+    WouldBlock { endpoint_name: String },
+    /// the endpoint didn't become ready within the timeout
+    #[snafu(display("endpoint {} not ready within {:?}", endpoint_name, timeout))]
+    Timeout {
+        endpoint_name: String,
+        timeout: Duration,
+    },
     /// message overflow the buffer
     MessageBufferOverflow {
         capacity: usize,
@@ -82,6 +190,17 @@ pub enum ChannelError {
     },
 }
 
+impl ChannelError {
+    /// true when one received message was dropped, too large or corrupted,
+    /// the channel still works and the next message can be read
+    pub fn is_bad_message(&self) -> bool {
+        matches!(
+            self,
+            ChannelError::MessageBufferOverflow { .. } | ChannelError::ChecksumMismatch { .. }
+        )
+    }
+}
+
 /// struct of rpmsg endpoint information
 /// We send this data structure to kernel, it requires a C-like struct
 #[derive(Clone, Debug)]
@@ -89,14 +208,21 @@ pub enum ChannelError {
 pub struct RPMsgEndpointInfo {
     // c_char in aarch64 is u8 in rust
     // c_char in x86 is i8 in rust
-    name: [c_char; 32],
+    name: [c_char; RPMSG_NAME_SIZE],
     src: __u32,
     dst: __u32,
 }
 impl RPMsgEndpointInfo {
     pub fn new(channel_name: &str, src: u32, dst: u32) -> Result<RPMsgEndpointInfo, ChannelError> {
+        // why: the last byte stays 0, the kernel expects a NUL terminated name
+        if channel_name.len() >= RPMSG_NAME_SIZE {
+            return Err(ChannelError::EndpointNameTooLong {
+                name: channel_name.to_string(),
+                max_len: RPMSG_NAME_SIZE - 1,
+            });
+        }
         let mut eptinfo = RPMsgEndpointInfo {
-            name: [0; 32],
+            name: [0; RPMSG_NAME_SIZE],
             src,
             dst,
         };
@@ -123,17 +249,97 @@ impl RPMsgEndpointInfo {
         Ok(eptinfo)
     }
 }
+/// the endpoint to create through the control interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointConfig {
+    /// the name of the endpoint, at most RPMSG_NAME_SIZE - 1 bytes, longer names are
+    /// refused with EndpointNameTooLong when the endpoint is created
+    pub name: String,
+    /// local address, RPMSG_ADDR_ANY lets the kernel pick one
+    pub src: u32,
+    /// remote address, RPMSG_ADDR_ANY when the remote picks it up from the first message
+    pub dst: u32,
+}
+
+impl EndpointConfig {
+    pub fn new(name: &str, src: u32, dst: u32) -> EndpointConfig {
+        EndpointConfig {
+            name: name.to_string(),
+            src,
+            dst,
+        }
+    }
+
+    /// an endpoint with both addresses picked at runtime
+    pub fn any(name: &str) -> EndpointConfig {
+        EndpointConfig::new(name, RPMSG_ADDR_ANY, RPMSG_ADDR_ANY)
+    }
+}
+
+/// # Channel config
+/// Which rpmsg device to use, which driver to bind to it and the endpoint to create.
+/// The default is the demo channel announced by the echo_test firmware.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelConfig {
+    pub virtio_id: String,
+    /// the name of the channel announced by the remote, also the name of the endpoint
+    pub channel_name: String,
+    pub version: String,
+    /// RPMSG_CHRDEV_DRIVER or RPMSG_CHAR_NOTIFY_DRIVER
+    pub driver_name: String,
+    /// local address of the endpoint
+    pub src: u32,
+    /// remote address of the endpoint
+    pub dst: u32,
+    /// how long to wait for the remote to announce the device
+    pub device_timeout: Duration,
+    /// append a CRC32 to every message and verify it on receive, the firmware has to do the same
+    pub checksum: bool,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        ChannelConfig {
+            virtio_id: "virtio0".to_string(),
+            channel_name: "rpmsg-openamp-demo-channel".to_string(),
+            version: "1.0".to_string(),
+            driver_name: RPMSG_CHAR_NOTIFY_DRIVER.to_string(),
+            src: RPMSG_ADDR_ANY,
+            dst: 0,
+            device_timeout: DEFAULT_DEVICE_TIMEOUT,
+            checksum: false,
+        }
+    }
+}
+
+impl ChannelConfig {
+    /// the name of the rpmsg device on the bus
+    pub fn device_name(&self) -> Result<RPMsgDeviceName, ChannelError> {
+        RPMsgDeviceName::with_version(&self.virtio_id, &self.channel_name, &self.version)
+    }
+
+    /// the endpoint created on the device
+    pub fn endpoint(&self) -> EndpointConfig {
+        EndpointConfig::new(&self.channel_name, self.src, self.dst)
+    }
+}
+
 // create a function to use ioctl system call for creating a endpoint
 ioctl_write_buf!(ioctl_create_endpt, 0xb5, 0x1, RPMsgEndpointInfo);
+// create a function to destroy the endpoint behind the descriptor, the counterpart of ioctl_create_endpt
+ioctl_none!(ioctl_destroy_endpt, 0xb5, 0x2);
 
 /// the struct of endpoint created by rpmsg character driver
-#[derive(Debug, Clone)]
+/// the descriptor is closed when the endpoint is dropped
+#[derive(Debug)]
 pub struct RPMsgEndpoint {
     // the path to the interface in system
     name: String,
     // initialized handler of the interface
-    // the default value is none
-    endpoint_handler: i32,
+    endpoint_handler: OwnedFd,
+    counters: EndpointCounters,
+    // every message is followed by its CRC32, see the checksum module
+    checksum: bool,
 }
 
 impl RPMsgEndpoint {
@@ -153,45 +359,264 @@ impl RPMsgEndpoint {
             })?;
             Ok(RPMsgEndpoint {
                 name: endpoint_name,
-                endpoint_handler,
+                // why: open returned a new descriptor which nobody else owns
+                endpoint_handler: unsafe { OwnedFd::from_raw_fd(endpoint_handler) },
+                counters: EndpointCounters::default(),
+                checksum: false,
             })
         } else {
             Err(ChannelError::FailedToCreateEndpoint { endpoint_name })
         }
     }
+    /// wrap a descriptor which is already opened in non-blocking mode
+    /// the descriptor must preserve message boundaries like an rpmsg endpoint does
+    pub(crate) fn from_owned_fd(endpoint_name: String, endpoint_handler: OwnedFd) -> RPMsgEndpoint {
+        RPMsgEndpoint {
+            name: endpoint_name,
+            endpoint_handler,
+            counters: EndpointCounters::default(),
+            checksum: false,
+        }
+    }
+    /// the path to the interface in system
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// the traffic and errors seen since the endpoint was opened or the last reset
+    pub fn stats(&self) -> EndpointStats {
+        self.counters.snapshot()
+    }
+    pub fn reset_stats(&self) {
+        self.counters.reset()
+    }
+    /// turn the checksum framing on or off, both sides have to agree on it
+    pub fn set_checksum(&mut self, enabled: bool) {
+        self.checksum = enabled;
+    }
+    pub fn checksum(&self) -> bool {
+        self.checksum
+    }
+    /// the bytes the framing adds to every message
+    pub fn frame_overhead(&self) -> usize {
+        if self.checksum {
+            CHECKSUM_LEN
+        } else {
+            0
+        }
+    }
+    /// the largest message which fits in one rpmsg buffer with the framing of the endpoint
+    pub fn max_message_size(&self) -> usize {
+        MAX_RPMSG_BUFF_SIZE as usize - self.frame_overhead()
+    }
+    /// ask the rpmsg character driver to destroy the endpoint
+    /// the descriptor stays open, it is closed by close or drop
+    pub fn destroy(&self) -> Result<(), ChannelError> {
+        trace!("destroying endpoint: {}", self.name);
+        unsafe { ioctl_destroy_endpt(self.endpoint_handler.as_raw_fd()) }.context(
+            FailedToDestroyEndpoint {
+                endpoint_name: self.name.clone(),
+            },
+        )?;
+        Ok(())
+    }
+    /// close the descriptor of the endpoint and report the error drop would swallow
+    pub fn close(self) -> Result<(), ChannelError> {
+        close(self.endpoint_handler.into_raw_fd())
+            .context(FailedToCloseFileError { path: self.name })
+    }
     // send the message through endpoint, return Channel Error
     pub fn send(&mut self, message: &[u8]) -> Result<(), ChannelError> {
         trace!("sending through the enpoint: {} bytes", message.len());
-        let bytes_sent = write(self.endpoint_handler, message)?;
+        let framed;
+        let message = if self.checksum {
+            framed = checksum::frame(message);
+            &framed[..]
+        } else {
+            message
+        };
+        let bytes_sent =
+            write(self.endpoint_handler.as_raw_fd(), message).map_err(|e| self.sys_error(e))?;
         if bytes_sent != message.len() {
+            self.counters.short_write();
             return Err(ChannelError::FailedToSend {
                 endpoint_name: self.name.clone(),
             });
         }
+        self.counters.sent(bytes_sent);
         Ok(())
     }
     /// try to read the message from endpoint and return the message buffer
     /// the capacity set the largest size of message we could receive
-    /// return error when there is no data or endpoint is not working
+    /// return WouldBlock when there is no data, or another error when endpoint is not working
     pub fn read(&mut self, capacity: usize) -> Result<Vec<u8>, ChannelError> {
-        //let mut buf: Vec<u8> = Vec::with_capacity(capacity);
-        let mut buf = vec![0; 1024];
-        let size = read(self.endpoint_handler, &mut buf)?;
-        if size > capacity {
-            Err(ChannelError::MessageBufferOverflow {
+        let overhead = self.frame_overhead();
+        // why: one spare byte tells a message which doesn't fit, the kernel drops the rest of it
+        let mut buf = vec![0; capacity + overhead + 1];
+        let size = self.read_frame(&mut buf)?;
+        if size > capacity + overhead {
+            self.counters.overflow();
+            return Err(ChannelError::MessageBufferOverflow {
                 capacity,
-                message_size: size,
-            })
-        } else {
-            // why: vector was created with length 0, now set it to the length of message
-            unsafe {
-                buf.set_len(size);
+                message_size: size - overhead,
+            });
+        }
+        let size = self.verify_frame(&buf[..size])?;
+        buf.truncate(size);
+        Ok(buf)
+    }
+    /// read one message into buf without allocating and return its size
+    /// buf only needs room for the message, not for the checksum of the framing,
+    /// a message which doesn't fit is dropped and reported as MessageBufferOverflow
+    pub fn read_into(&mut self, buf: &mut [u8]) -> Result<usize, ChannelError> {
+        // why: the kernel cuts a message at the end of the buffer, the spare buffer takes
+        // the checksum and the rest of a message too large for buf, whatever its size
+        let mut spare = [0u8; MAX_RPMSG_BUFF_SIZE as usize];
+        let size = self.read_frame_vectored(buf, &mut spare)?;
+        let overhead = self.frame_overhead();
+        if size > buf.len() + overhead {
+            self.counters.overflow();
+            return Err(ChannelError::MessageBufferOverflow {
+                capacity: buf.len(),
+                message_size: size - overhead,
+            });
+        }
+        if size <= buf.len() {
+            return self.verify_frame(&buf[..size]);
+        }
+        let spilled = &spare[..size - buf.len()];
+        if size < overhead {
+            // a frame shorter than a checksum, verify_frame reports it
+            let mut frame = [0u8; CHECKSUM_LEN];
+            frame[..buf.len()].copy_from_slice(buf);
+            frame[buf.len()..size].copy_from_slice(spilled);
+            return self.verify_frame(&frame[..size]);
+        }
+        // the message is in buf, its checksum is split between buf and spare
+        let message_size = size - overhead;
+        let mut checksum = [0u8; CHECKSUM_LEN];
+        let in_buf = buf.len() - message_size;
+        checksum[..in_buf].copy_from_slice(&buf[message_size..]);
+        checksum[in_buf..].copy_from_slice(spilled);
+        checksum::verify_message(&buf[..message_size], checksum)
+            .map_err(|(received, computed)| self.checksum_mismatch(received, computed))
+    }
+
+    fn read_frame(&mut self, buf: &mut [u8]) -> Result<usize, ChannelError> {
+        let size = read(self.endpoint_handler.as_raw_fd(), buf).map_err(|e| self.sys_error(e))?;
+        self.frame_received(size);
+        Ok(size)
+    }
+
+    // read one message into buf, and into spare what doesn't fit in buf
+    fn read_frame_vectored(
+        &mut self,
+        buf: &mut [u8],
+        spare: &mut [u8],
+    ) -> Result<usize, ChannelError> {
+        let mut iov = [IoVec::from_mut_slice(buf), IoVec::from_mut_slice(spare)];
+        let size =
+            readv(self.endpoint_handler.as_raw_fd(), &mut iov).map_err(|e| self.sys_error(e))?;
+        self.frame_received(size);
+        Ok(size)
+    }
+
+    fn frame_received(&self, size: usize) {
+        // why: an empty read is the peer hanging up, not a message
+        if size > 0 {
+            self.counters.received(size);
+        }
+    }
+
+    // the size of the message in front of the checksum
+    fn verify_frame(&self, frame: &[u8]) -> Result<usize, ChannelError> {
+        if !self.checksum || frame.is_empty() {
+            return Ok(frame.len());
+        }
+        checksum::verify(frame)
+            .map_err(|(received, computed)| self.checksum_mismatch(received, computed))
+    }
+
+    fn checksum_mismatch(&self, received: u32, computed: u32) -> ChannelError {
+        self.counters.checksum_mismatch();
+        ChannelError::ChecksumMismatch {
+            endpoint_name: self.name.clone(),
+            received,
+            computed,
+        }
+    }
+    /// wait up to timeout for a message and read it, return Timeout when none arrived
+    pub fn read_timeout(
+        &mut self,
+        capacity: usize,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, ChannelError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.wait_ready(PollFlags::POLLIN, deadline, timeout)?;
+            match self.read(capacity) {
+                // why: poll may report a message another reader of the descriptor took first
+                Err(ChannelError::WouldBlock { .. }) => continue,
+                result => return result,
             }
-            Ok(buf)
+        }
+    }
+    /// wait up to timeout until a message can be read, return Timeout otherwise
+    /// a hang up counts as readable, the read then reports it
+    pub fn wait_readable(&self, timeout: Duration) -> Result<(), ChannelError> {
+        self.wait_ready(PollFlags::POLLIN, Instant::now() + timeout, timeout)
+    }
+    /// wait up to timeout until a message can be sent, return Timeout otherwise
+    pub fn wait_writable(&self, timeout: Duration) -> Result<(), ChannelError> {
+        self.wait_ready(PollFlags::POLLOUT, Instant::now() + timeout, timeout)
+    }
+
+    fn wait_ready(
+        &self,
+        events: PollFlags,
+        deadline: Instant,
+        timeout: Duration,
+    ) -> Result<(), ChannelError> {
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut fds = [PollFd::new(self.endpoint_handler.as_raw_fd(), events)];
+            // why: round up, a timeout below one millisecond must still wait instead of spin
+            let timeout_ms = remaining
+                .as_nanos()
+                .div_ceil(1_000_000)
+                .min(i32::MAX as u128) as i32;
+            match poll(&mut fds, timeout_ms) {
+                Ok(0) => {
+                    return Err(ChannelError::Timeout {
+                        endpoint_name: self.name.clone(),
+                        timeout,
+                    })
+                }
+                Ok(_) => return Ok(()),
+                Err(nix::Error::EINTR) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    fn sys_error(&self, error: nix::Error) -> ChannelError {
+        match error {
+            nix::Error::EAGAIN => {
+                self.counters.would_block();
+                ChannelError::WouldBlock {
+                    endpoint_name: self.name.clone(),
+                }
+            }
+            source => ChannelError::SysError { source },
         }
     }
 }
 
+impl AsRawFd for RPMsgEndpoint {
+    fn as_raw_fd(&self) -> RawFd {
+        self.endpoint_handler.as_raw_fd()
+    }
+}
+
 /// # Abstract
 /// This trait defines the contract for Channel in a generic fashion
 pub trait AbstractRPMsgChannel: Sized {
@@ -202,61 +627,166 @@ pub trait AbstractRPMsgChannel: Sized {
     ) -> Result<Self, ChannelError>;
     fn send(&mut self, message: &[u8]) -> Result<(), ChannelError>;
     fn read(&mut self, capacity: usize) -> Result<Vec<u8>, ChannelError>;
+    /// the largest message send accepts, one rpmsg buffer less the framing of the channel
+    fn max_message_size(&self) -> usize {
+        MAX_RPMSG_BUFF_SIZE as usize
+    }
+    /// read one message into buf and return its size
+    /// the default goes through read and allocates, channels backed by an endpoint don't
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<usize, ChannelError> {
+        let message = self.read(buf.len())?;
+        buf[..message.len()].copy_from_slice(&message);
+        Ok(message.len())
+    }
 }
 
+/// the channel owns the control interface and the endpoint it created,
+/// the endpoint is destroyed and both descriptors are closed on drop
 pub struct OctRPMsgChannel {
     // the name of connected rpmsg_device
     _rpmsg_device_name: String,
     // the name of control interface
-    _ctrl_interface_name: String,
-    // the handler to control interface
-    _ctrl_interface_handler: i32,
-    // the endpoint for message passing
-    endpoint: RPMsgEndpoint,
+    ctrl_interface_name: String,
+    // the handler to control interface, only taken away by close
+    ctrl_interface_handler: Option<OwnedFd>,
+    // the endpoint for message passing, only taken away by close
+    endpoint: Option<RPMsgEndpoint>,
 }
 
 /// register the driver specified by driver_name to the device specified by device_name
 pub fn register_rpmsg_driver_for_device(
+    sysfs: &SysfsRoot,
     device_name: String,
     driver_name: String,
 ) -> Result<(), io::Error> {
-    let driver_api = format!("/sys/bus/rpmsg/devices/{}/driver_override", device_name);
-    let mut fd = OpenOptions::new().write(true).open(driver_api).unwrap();
+    let driver_api = sysfs.rpmsg_device_dir(&device_name).join("driver_override");
+    let mut fd = OpenOptions::new().write(true).open(driver_api)?;
     fd.write_all(driver_name.as_bytes())?;
-    let driver_bind_api = format!("/sys/bus/rpmsg/drivers/{}/bind", driver_name);
-    let mut fd = OpenOptions::new()
-        .write(true)
-        .open(driver_bind_api)
-        .unwrap();
+    let driver_bind_api = sysfs.rpmsg_driver_dir(&driver_name).join("bind");
+    let mut fd = OpenOptions::new().write(true).open(driver_bind_api)?;
     fd.write_all(device_name.as_bytes())?;
     Ok(())
 }
 
+/// wait up to timeout for the remote to announce device_name, return the path to the device
+/// on the system sysfs the wait blocks on the uevents of the kernel, sysfs itself doesn't
+/// report new entries through inotify. Under a fake root, where no uevent is sent,
+/// inotify reports the new entries instead. The device is checked every
+/// DEVICE_RECHECK_INTERVAL when neither is available.
+pub fn wait_for_rpmsg_device(
+    sysfs: &SysfsRoot,
+    device_name: &str,
+    timeout: Duration,
+) -> Result<PathBuf, ChannelError> {
+    let devices_dir = sysfs.rpmsg_devices_dir();
+    let device_path = devices_dir.join(device_name);
+    let deadline = Instant::now() + timeout;
+    let uevents = if sysfs.is_system() {
+        // why: open before the first check, a device added in between is not missed
+        UeventSocket::open()
+            .map_err(|e| warn!("no uevents, polling for {}: {}", device_name, e))
+            .ok()
+    } else {
+        None
+    };
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+    // why: Inotify doesn't close its descriptor, the guard closes it when we return
+    let _inotify_guard = unsafe { OwnedFd::from_raw_fd(inotify.as_raw_fd()) };
+    let mut watching = false;
+    loop {
+        // why: the directory itself shows up late when the rpmsg bus is loaded after us
+        if uevents.is_none() && !watching {
+            watching = inotify
+                .add_watch(
+                    &devices_dir,
+                    AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO,
+                )
+                .is_ok();
+        }
+        if device_path.exists() {
+            trace!("rpmsg device {} is ready", device_name);
+            return Ok(device_path);
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(ChannelError::DeviceTimeout {
+                device_name: device_name.to_string(),
+                timeout,
+            });
+        }
+        let (fd, wait) = match &uevents {
+            Some(uevents) => (uevents.as_raw_fd(), remaining),
+            None if watching => (inotify.as_raw_fd(), remaining),
+            None => (inotify.as_raw_fd(), remaining.min(DEVICE_RECHECK_INTERVAL)),
+        };
+        let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+        match poll(&mut fds, wait.as_millis().clamp(1, i32::MAX as u128) as i32) {
+            Ok(_) | Err(nix::Error::EINTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+        // drop the events, the check above tells whether the device arrived
+        match &uevents {
+            Some(uevents) => match uevents.read_events() {
+                Ok(events) => {
+                    if events
+                        .iter()
+                        .any(|event| event.announces_rpmsg_device(device_name))
+                    {
+                        trace!("the kernel announced rpmsg device {}", device_name);
+                    }
+                }
+                // why: the socket overflows in the burst of uevents of a remoteproc boot,
+                // the lost uevents may include the device, the check above rescans sysfs
+                Err(e) if e.raw_os_error() == Some(nix::libc::ENOBUFS) => {
+                    trace!("uevents were lost, checking {} again", device_name);
+                }
+                Err(e) => {
+                    return Err(ChannelError::IOError {
+                        error: format!("{:?}", e),
+                    })
+                }
+            },
+            None => loop {
+                match inotify.read_events() {
+                    Ok(events) if !events.is_empty() => {}
+                    Ok(_) | Err(nix::Error::EAGAIN) => break,
+                    Err(e) => {
+                        trace!(
+                            "can't read inotify events, checking {} again: {}",
+                            device_name,
+                            e
+                        );
+                        break;
+                    }
+                }
+            },
+        }
+    }
+}
+
 /// search the control interface exposed by the driver to the device specified by device_name
 pub fn search_control_interface(
+    sysfs: &SysfsRoot,
     device_name: String,
     ctrl_prefix: String,
 ) -> Result<String, io::Error> {
-    let ctrl_interface_dir_path_str = format!("/sys/bus/rpmsg/devices/{}/rpmsg", device_name);
-    let ctrl_interface_dir_path = Path::new(&ctrl_interface_dir_path_str);
+    let ctrl_interface_dir_path = sysfs.rpmsg_device_dir(&device_name).join("rpmsg");
     let dir_content = fs::read_dir(ctrl_interface_dir_path)?;
     for entry in dir_content {
         let path = entry?.path();
 
-        if let Some(path_str) = path.to_str() {
-            if path_str.contains(&ctrl_prefix) {
-                if let Some(interface_id) = path.file_name() {
-                    return Ok(interface_id
-                        .to_str()
-                        .ok_or(io::ErrorKind::InvalidData {})?
-                        .to_string());
-                } else {
-                    error!("can't get interface id from path {:?}", path);
-                    continue;
+        // only look at the last component, the root of sysfs could be anywhere
+        if let Some(interface_id) = path.file_name() {
+            if let Some(interface_id) = interface_id.to_str() {
+                if interface_id.contains(&ctrl_prefix) {
+                    return Ok(interface_id.to_string());
                 }
+            } else {
+                error!("can't convert {:?} to string", path);
+                continue;
             }
         } else {
-            error!("can't convert {:?} to string", path);
+            error!("can't get interface id from path {:?}", path);
             continue;
         }
     }
@@ -268,96 +798,645 @@ pub fn search_control_interface(
 
 /// seach the endpoint name created by control interface.(I don't know why we need to search instead of get it from somewhere)
 pub fn search_endpoint_path_by_name(
+    sysfs: &SysfsRoot,
     ctrl_interface_name: String,
     endpoint_name: String,
+) -> Result<String, ChannelError> {
+    search_endpoint_path(
+        sysfs,
+        ctrl_interface_name,
+        &EndpointConfig::any(&endpoint_name),
+    )
+}
+
+/// search the endpoint created by control interface for config
+/// the name has to match, the local address only when config asks for a specific one
+pub fn search_endpoint_path(
+    sysfs: &SysfsRoot,
+    ctrl_interface_name: String,
+    config: &EndpointConfig,
 ) -> Result<String, ChannelError> {
     for i in 0..128 {
-        let rpmsg_ept_name_registry_path_str =
-            format!("/sys/class/rpmsg/{0}/rpmsg{1}/name", ctrl_interface_name, i);
-        let rpmsg_ept_name_registry_path = Path::new(&rpmsg_ept_name_registry_path_str);
-        if access(rpmsg_ept_name_registry_path, AccessFlags::F_OK).is_err() {
+        let rpmsg_ept_registry_path = sysfs
+            .rpmsg_class_dir()
+            .join(&ctrl_interface_name)
+            .join(format!("rpmsg{}", i));
+        let rpmsg_ept_name_registry_path = rpmsg_ept_registry_path.join("name");
+        if access(&rpmsg_ept_name_registry_path, AccessFlags::F_OK).is_err() {
             continue;
         }
 
         // fetch name of candidate endpoint
-        let mut fd = OpenOptions::new()
-            .read(true)
-            .open(rpmsg_ept_name_registry_path)
-            .unwrap();
-        let mut candidate_endpoint = String::new();
-        fd.read_to_string(&mut candidate_endpoint)
-            .map_err(|e| ChannelError::IOError {
-                error: format!("{:?}", e),
-            })?;
-        // the name extracted from the interface appended with \n
-        if candidate_endpoint.ends_with('\n') {
-            candidate_endpoint.pop();
-        }
+        let candidate_endpoint = read_sysfs_attribute(&rpmsg_ept_name_registry_path)?;
         //println!("candidate endpoint: {:?}", candidate_endpoint);
         //println!("target endpoint: {:?}", endpoint_name);
-        if endpoint_name.eq(&candidate_endpoint) {
-            trace!("found path for enpoint {}", endpoint_name);
-            return Ok(format!("/dev/rpmsg{}", i));
+        if !config.name.eq(&candidate_endpoint) {
+            continue;
+        }
+        // an endpoint with the same name could be left over with another address
+        if config.src != RPMSG_ADDR_ANY {
+            let src_registry_path = rpmsg_ept_registry_path.join("src");
+            if access(&src_registry_path, AccessFlags::F_OK).is_ok() {
+                let src = read_sysfs_attribute(&src_registry_path)?;
+                if parse_rpmsg_addr(&src) != Some(config.src) {
+                    continue;
+                }
+            }
         }
+        trace!("found path for enpoint {}", config.name);
+        return sysfs
+            .dev_node(&format!("rpmsg{}", i))
+            .to_str()
+            .map(|path| path.to_string())
+            .ok_or(ChannelError::OsStrConversion {});
     }
-    Err(ChannelError::FailedToCreateEndpoint { endpoint_name })
+    Err(ChannelError::FailedToCreateEndpoint {
+        endpoint_name: config.name.clone(),
+    })
 }
 
-impl AbstractRPMsgChannel for OctRPMsgChannel {
-    fn instantiate(
+/// read a sysfs attribute without the trailing new line
+pub(crate) fn read_sysfs_attribute(path: &Path) -> Result<String, ChannelError> {
+    let mut fd = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .map_err(|e| ChannelError::IOError {
+            error: format!("{:?}", e),
+        })?;
+    let mut attribute = String::new();
+    fd.read_to_string(&mut attribute)
+        .map_err(|e| ChannelError::IOError {
+            error: format!("{:?}", e),
+        })?;
+    // the value extracted from the interface appended with \n
+    if attribute.ends_with('\n') {
+        attribute.pop();
+    }
+    Ok(attribute)
+}
+
+/// rpmsg addresses show up in sysfs either as hex (0x400) or as signed decimal (-1 for any)
+pub(crate) fn parse_rpmsg_addr(addr: &str) -> Option<u32> {
+    let addr = addr.trim();
+    if let Some(hex) = addr.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()
+    } else {
+        addr.parse::<i64>().ok().map(|addr| addr as u32)
+    }
+}
+
+/// bind driver_name to the rpmsg device and open the control interface it exposes
+pub(crate) fn open_control_interface(
+    sysfs: &SysfsRoot,
+    device_name: &str,
+    driver_name: &str,
+) -> Result<(String, OwnedFd), ChannelError> {
+    // register RPMsg driver
+    register_rpmsg_driver_for_device(sysfs, device_name.to_string(), driver_name.to_string())
+        .map_err(|e| ChannelError::IOError {
+            error: format!("{:?}", e),
+        })?;
+    trace!("Register rpmsg driver");
+
+    // look for control interface of character driver
+    let ctrl_interface_name =
+        search_control_interface(sysfs, device_name.to_string(), "rpmsg_ctrl".to_string())
+            .map_err(|e| ChannelError::IOError {
+                error: format!("{:?}", e),
+            })?;
+    let ctrl_interface_path = sysfs.dev_node(&ctrl_interface_name);
+    let ctrl_interface_handler = open(&ctrl_interface_path, OFlag::O_RDWR, Mode::empty())?;
+    // why: open returned a new descriptor which nobody else owns
+    let ctrl_interface_handler = unsafe { OwnedFd::from_raw_fd(ctrl_interface_handler) };
+    Ok((ctrl_interface_name, ctrl_interface_handler))
+}
+
+/// create the endpoint described by config through the control interface and open it
+fn create_endpoint(
+    sysfs: &SysfsRoot,
+    ctrl_interface_name: &str,
+    ctrl_interface_handler: &OwnedFd,
+    config: &EndpointConfig,
+) -> Result<RPMsgEndpoint, ChannelError> {
+    let endpoint_path_str =
+        create_endpoint_node(sysfs, ctrl_interface_name, ctrl_interface_handler, config)?;
+    RPMsgEndpoint::new(endpoint_path_str)
+}
+
+/// create the endpoint described by config through the control interface,
+/// return the path to its device node
+pub(crate) fn create_endpoint_node(
+    sysfs: &SysfsRoot,
+    ctrl_interface_name: &str,
+    ctrl_interface_handler: &OwnedFd,
+    config: &EndpointConfig,
+) -> Result<String, ChannelError> {
+    let endpoint = RPMsgEndpointInfo::new(&config.name, config.src, config.dst)?;
+    trace!("creating endpoint: {}", config.name);
+    let ret = unsafe { ioctl_create_endpt(ctrl_interface_handler.as_raw_fd(), &[endpoint])? };
+    if ret == -1 {
+        return Err(ChannelError::FailedToCreateEndpoint {
+            endpoint_name: config.name.clone(),
+        });
+    }
+    search_endpoint_path(sysfs, ctrl_interface_name.to_string(), config)
+}
+
+/// close the control interface and report the error drop would swallow
+fn close_control_interface(
+    ctrl_interface_name: &str,
+    ctrl_interface_handler: OwnedFd,
+) -> Result<(), ChannelError> {
+    close(ctrl_interface_handler.into_raw_fd()).context(FailedToCloseFileError {
+        path: ctrl_interface_name.to_string(),
+    })
+}
+
+impl OctRPMsgChannel {
+    /// instantiate the channel with sysfs and devfs located under sysfs
+    pub fn instantiate_in(
+        sysfs: &SysfsRoot,
         channel_name: String,
         virtio_id: String,
         version_number: String,
     ) -> Result<Self, ChannelError> {
-        trace!("Open rpmsg dev {}", channel_name);
-        let device_name = format!("{}.{}.-{}", virtio_id, channel_name, version_number);
-        let rpmsg_device_path = Path::new("/sys/bus/rpmsg/devices").join(device_name.clone());
+        let config = ChannelConfig {
+            virtio_id,
+            channel_name,
+            version: version_number,
+            driver_name: RPMSG_CHRDEV_DRIVER.to_string(),
+            dst: RPMSG_ADDR_ANY,
+            ..ChannelConfig::default()
+        };
 
         // condition compilation for waitting RPU response
-        if cfg!(test) || cfg!(feature = "debug") {
-            while access(&rpmsg_device_path, AccessFlags::F_OK).is_err() {}
-        } else if access(&rpmsg_device_path, AccessFlags::F_OK).is_err() {
-            return Err(ChannelError::FailToAccessDevice { device_name });
+        if !(cfg!(test) || cfg!(feature = "debug")) {
+            let device_name = config.device_name()?.to_string();
+            if access(&sysfs.rpmsg_device_dir(&device_name), AccessFlags::F_OK).is_err() {
+                return Err(ChannelError::FailToAccessDevice { device_name });
+            }
         }
+        OctRPMsgChannel::open_in(sysfs, &config)
+    }
+
+    /// open the channel described by config, waiting up to config.device_timeout for the device
+    pub fn open(config: &ChannelConfig) -> Result<Self, ChannelError> {
+        OctRPMsgChannel::open_in(&SysfsRoot::default(), config)
+    }
 
-        // register RPMsg driver
-        let rpmsg_driver_name = String::from("rpmsg_chrdev");
-        register_rpmsg_driver_for_device(device_name.clone(), rpmsg_driver_name).unwrap();
-        trace!("Register rpmsg driver");
+    /// same as open, with sysfs and devfs located under sysfs
+    pub fn open_in(sysfs: &SysfsRoot, config: &ChannelConfig) -> Result<Self, ChannelError> {
+        let device_name = config.device_name()?.to_string();
+        trace!("Open rpmsg dev {}", device_name);
+        wait_for_rpmsg_device(sysfs, &device_name, config.device_timeout)?;
 
-        // look for control interface of character driver
-        let ctrl_interface_name =
-            search_control_interface(device_name.clone(), "rpmsg_ctrl".to_string()).unwrap();
-        let ctrl_interface_path = Path::new("/dev").join(ctrl_interface_name.clone());
-        let ctrl_interface_handler = open(&ctrl_interface_path, OFlag::O_RDWR, Mode::empty())?;
+        let (ctrl_interface_name, ctrl_interface_handler) =
+            open_control_interface(sysfs, &device_name, &config.driver_name)?;
 
         // create endpoint
-        let endpoint = RPMsgEndpointInfo::new(&channel_name, RPMSG_ADDR_ANY, RPMSG_ADDR_ANY)?;
-        trace!("creating endpoint: {}", channel_name);
-        let ret = unsafe { ioctl_create_endpt(ctrl_interface_handler, &[endpoint])? };
-        if ret == -1 {
-            return Err(ChannelError::FailedToCreateEndpoint {
-                endpoint_name: channel_name,
-            });
-        }
-        let endpoint_path_str =
-            search_endpoint_path_by_name(ctrl_interface_name.clone(), channel_name)?;
-        let endpoint = RPMsgEndpoint::new(endpoint_path_str)?;
+        let mut endpoint = create_endpoint(
+            sysfs,
+            &ctrl_interface_name,
+            &ctrl_interface_handler,
+            &config.endpoint(),
+        )?;
+        endpoint.set_checksum(config.checksum);
 
         Ok(OctRPMsgChannel {
             _rpmsg_device_name: device_name,
-            _ctrl_interface_name: ctrl_interface_name,
-            _ctrl_interface_handler: ctrl_interface_handler,
-            endpoint,
+            ctrl_interface_name,
+            ctrl_interface_handler: Some(ctrl_interface_handler),
+            endpoint: Some(endpoint),
         })
     }
+    /// destroy the endpoint and close the descriptors, report the first error
+    /// every step is tried even if a previous one failed
+    pub fn close(mut self) -> Result<(), ChannelError> {
+        self.teardown()
+    }
+
+    fn teardown(&mut self) -> Result<(), ChannelError> {
+        let mut result = Ok(());
+        if let Some(endpoint) = self.endpoint.take() {
+            info!("closing endpoint {}: {}", endpoint.name(), endpoint.stats());
+            result = result.and(endpoint.destroy());
+            result = result.and(endpoint.close());
+        }
+        if let Some(ctrl_interface_handler) = self.ctrl_interface_handler.take() {
+            result = result.and(close_control_interface(
+                &self.ctrl_interface_name,
+                ctrl_interface_handler,
+            ));
+        }
+        result
+    }
+
+    /// turn the checksum framing on or off, see RPMsgEndpoint::set_checksum
+    pub fn set_checksum(&mut self, enabled: bool) {
+        self.endpoint_mut().set_checksum(enabled)
+    }
+
+    /// wait up to timeout for a message and read it, see RPMsgEndpoint::read_timeout
+    pub fn read_timeout(
+        &mut self,
+        capacity: usize,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, ChannelError> {
+        self.endpoint_mut().read_timeout(capacity, timeout)
+    }
+    /// wait up to timeout until a message can be read
+    pub fn wait_readable(&mut self, timeout: Duration) -> Result<(), ChannelError> {
+        self.endpoint_mut().wait_readable(timeout)
+    }
+    /// wait up to timeout until a message can be sent
+    pub fn wait_writable(&mut self, timeout: Duration) -> Result<(), ChannelError> {
+        self.endpoint_mut().wait_writable(timeout)
+    }
+
+    /// the traffic and errors of the endpoint, see RPMsgEndpoint::stats
+    pub fn stats(&self) -> EndpointStats {
+        self.endpoint().stats()
+    }
+    pub fn reset_stats(&self) {
+        self.endpoint().reset_stats()
+    }
+
+    fn endpoint(&self) -> &RPMsgEndpoint {
+        self.endpoint
+            .as_ref()
+            .expect("endpoint is only taken away when the channel is closed")
+    }
+
+    fn endpoint_mut(&mut self) -> &mut RPMsgEndpoint {
+        self.endpoint
+            .as_mut()
+            .expect("endpoint is only taken away when the channel is closed")
+    }
+}
+
+impl Drop for OctRPMsgChannel {
+    fn drop(&mut self) {
+        if let Err(e) = self.teardown() {
+            warn!("failed to tear down the channel: {}", e);
+        }
+    }
+}
+
+impl AsRawFd for OctRPMsgChannel {
+    /// the descriptor of the endpoint used for message passing
+    fn as_raw_fd(&self) -> RawFd {
+        self.endpoint().as_raw_fd()
+    }
+}
+
+impl AbstractRPMsgChannel for OctRPMsgChannel {
+    fn instantiate(
+        channel_name: String,
+        virtio_id: String,
+        version_number: String,
+    ) -> Result<Self, ChannelError> {
+        OctRPMsgChannel::instantiate_in(
+            &SysfsRoot::default(),
+            channel_name,
+            virtio_id,
+            version_number,
+        )
+    }
 
     fn send(&mut self, message: &[u8]) -> Result<(), ChannelError> {
-        self.endpoint.send(message)
+        self.endpoint_mut().send(message)
+    }
+
+    fn max_message_size(&self) -> usize {
+        self.endpoint().max_message_size()
     }
 
     /// a wrapper function around endpoint read api
     fn read(&mut self, capacity: usize) -> Result<Vec<u8>, ChannelError> {
-        self.endpoint.read(capacity)
+        self.endpoint_mut().read(capacity)
+    }
+
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<usize, ChannelError> {
+        self.endpoint_mut().read_into(buf)
+    }
+}
+
+/// # Endpoint set
+/// Several endpoints created through the control interface of one rpmsg device,
+/// e.g. command, event and bulk-data services exposed by the same firmware.
+/// The endpoints are looked up by name, so the names in a set have to be unique.
+/// All endpoints are destroyed and all descriptors closed on drop.
+pub struct RPMsgEndpointSet {
+    // the name of connected rpmsg_device
+    rpmsg_device_name: String,
+    // the name of control interface
+    ctrl_interface_name: String,
+    // the handler to control interface, only taken away by close
+    ctrl_interface_handler: Option<OwnedFd>,
+    // the endpoints with their configured name, in the order of their configuration
+    endpoints: Vec<(String, RPMsgEndpoint)>,
+}
+
+impl RPMsgEndpointSet {
+    /// bind driver_name to device_name and create one endpoint per config
+    /// the endpoints created before a failure are destroyed again
+    pub fn open(
+        sysfs: &SysfsRoot,
+        device_name: &str,
+        driver_name: &str,
+        configs: &[EndpointConfig],
+    ) -> Result<RPMsgEndpointSet, ChannelError> {
+        for (i, config) in configs.iter().enumerate() {
+            if configs[..i].iter().any(|other| other.name == config.name) {
+                return Err(ChannelError::DuplicatedEndpoint {
+                    endpoint_name: config.name.clone(),
+                });
+            }
+        }
+        if access(&sysfs.rpmsg_device_dir(device_name), AccessFlags::F_OK).is_err() {
+            return Err(ChannelError::FailToAccessDevice {
+                device_name: device_name.to_string(),
+            });
+        }
+
+        let (ctrl_interface_name, ctrl_interface_handler) =
+            open_control_interface(sysfs, device_name, driver_name)?;
+        let mut set = RPMsgEndpointSet {
+            rpmsg_device_name: device_name.to_string(),
+            ctrl_interface_name,
+            ctrl_interface_handler: Some(ctrl_interface_handler),
+            endpoints: Vec::with_capacity(configs.len()),
+        };
+        for config in configs {
+            let endpoint = create_endpoint(
+                sysfs,
+                &set.ctrl_interface_name,
+                set.ctrl_interface_handler.as_ref().unwrap(),
+                config,
+            )?;
+            set.endpoints.push((config.name.clone(), endpoint));
+        }
+        Ok(set)
+    }
+
+    /// the name of the rpmsg device the endpoints were created on
+    pub fn device_name(&self) -> &str {
+        &self.rpmsg_device_name
+    }
+
+    /// the names of the endpoints in the order of their configuration
+    pub fn names(&self) -> Vec<String> {
+        self.endpoints
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    pub fn get(&self, endpoint_name: &str) -> Option<&RPMsgEndpoint> {
+        self.endpoints
+            .iter()
+            .find(|(name, _)| name == endpoint_name)
+            .map(|(_, endpoint)| endpoint)
+    }
+
+    pub fn get_mut(&mut self, endpoint_name: &str) -> Option<&mut RPMsgEndpoint> {
+        self.endpoints
+            .iter_mut()
+            .find(|(name, _)| name == endpoint_name)
+            .map(|(_, endpoint)| endpoint)
+    }
+
+    /// destroy the endpoints and close the descriptors, report the first error
+    /// every step is tried even if a previous one failed
+    pub fn close(mut self) -> Result<(), ChannelError> {
+        self.teardown()
+    }
+
+    fn teardown(&mut self) -> Result<(), ChannelError> {
+        let mut result = Ok(());
+        for (_, endpoint) in self.endpoints.drain(..) {
+            result = result.and(endpoint.destroy());
+            result = result.and(endpoint.close());
+        }
+        if let Some(ctrl_interface_handler) = self.ctrl_interface_handler.take() {
+            result = result.and(close_control_interface(
+                &self.ctrl_interface_name,
+                ctrl_interface_handler,
+            ));
+        }
+        result
+    }
+}
+
+impl Drop for RPMsgEndpointSet {
+    fn drop(&mut self) {
+        if let Err(e) = self.teardown() {
+            warn!("failed to tear down the endpoints: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::tests::{fake_endpoint, fake_rpmsg_tree};
+
+    const DEVICE_NAME: &str = "virtio0.rpmsg-openamp-demo-channel.-1.0";
+
+    #[test]
+    fn endpoint_name_fits_the_kernel_buffer() {
+        let longest = "e".repeat(RPMSG_NAME_SIZE - 1);
+        let info = RPMsgEndpointInfo::new(&longest, 0x400, RPMSG_ADDR_ANY).unwrap();
+        assert_eq!(info.name[RPMSG_NAME_SIZE - 1], 0);
+        assert_eq!(info.name[RPMSG_NAME_SIZE - 2], b'e' as c_char);
+        for len in [RPMSG_NAME_SIZE, RPMSG_NAME_SIZE + 1] {
+            assert_eq!(
+                RPMsgEndpointInfo::new(&"e".repeat(len), 0x400, RPMSG_ADDR_ANY).err(),
+                Some(ChannelError::EndpointNameTooLong {
+                    name: "e".repeat(len),
+                    max_len: RPMSG_NAME_SIZE - 1,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn register_driver_writes_override_and_bind() {
+        let root = tempfile::tempdir().unwrap();
+        let sysfs = fake_rpmsg_tree(root.path(), DEVICE_NAME, "rpmsg_chrdev");
+        register_rpmsg_driver_for_device(
+            &sysfs,
+            DEVICE_NAME.to_string(),
+            "rpmsg_chrdev".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(sysfs.rpmsg_device_dir(DEVICE_NAME).join("driver_override"))
+                .unwrap(),
+            "rpmsg_chrdev"
+        );
+        assert_eq!(
+            fs::read_to_string(sysfs.rpmsg_driver_dir("rpmsg_chrdev").join("bind")).unwrap(),
+            DEVICE_NAME
+        );
+    }
+
+    #[test]
+    fn register_driver_fails_without_device() {
+        let root = tempfile::tempdir().unwrap();
+        let sysfs = fake_rpmsg_tree(root.path(), DEVICE_NAME, "rpmsg_chrdev");
+        assert!(register_rpmsg_driver_for_device(
+            &sysfs,
+            "virtio1.missing.-1.0".to_string(),
+            "rpmsg_chrdev".to_string(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn discover_control_interface_and_endpoint() {
+        let root = tempfile::tempdir().unwrap();
+        let sysfs = fake_rpmsg_tree(root.path(), DEVICE_NAME, "rpmsg_chrdev");
+        fake_endpoint(&sysfs, 0, "other-channel");
+        fake_endpoint(&sysfs, 3, "rpmsg-openamp-demo-channel");
+
+        let ctrl_interface_name =
+            search_control_interface(&sysfs, DEVICE_NAME.to_string(), "rpmsg_ctrl".to_string())
+                .unwrap();
+        assert_eq!(ctrl_interface_name, "rpmsg_ctrl0");
+
+        let endpoint_path = search_endpoint_path_by_name(
+            &sysfs,
+            ctrl_interface_name,
+            "rpmsg-openamp-demo-channel".to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            Path::new(&endpoint_path),
+            sysfs.dev_node("rpmsg3").as_path()
+        );
+        assert!(RPMsgEndpoint::new(endpoint_path).is_ok());
+    }
+
+    #[test]
+    fn endpoint_reports_teardown_errors() {
+        let root = tempfile::tempdir().unwrap();
+        let sysfs = fake_rpmsg_tree(root.path(), DEVICE_NAME, "rpmsg_chrdev");
+        fake_endpoint(&sysfs, 0, "rpmsg-openamp-demo-channel");
+        let endpoint_path = sysfs.dev_node("rpmsg0").to_str().unwrap().to_string();
+        let endpoint = RPMsgEndpoint::new(endpoint_path.clone()).unwrap();
+        // a regular file doesn't understand the destroy ioctl of the character driver
+        assert!(matches!(
+            endpoint.destroy(),
+            Err(ChannelError::FailedToDestroyEndpoint { endpoint_name, .. }) if endpoint_name == endpoint_path
+        ));
+        assert_eq!(endpoint.close(), Ok(()));
+    }
+
+    #[test]
+    fn endpoints_with_the_same_name_are_told_apart_by_address() {
+        let root = tempfile::tempdir().unwrap();
+        let sysfs = fake_rpmsg_tree(root.path(), DEVICE_NAME, "rpmsg_chrdev");
+        fake_endpoint(&sysfs, 0, "rpmsg-events");
+        fake_endpoint(&sysfs, 1, "rpmsg-events");
+        fake_endpoint(&sysfs, 2, "rpmsg-bulk");
+        let endpoint_dir = sysfs.rpmsg_class_dir().join("rpmsg_ctrl0");
+        fs::write(endpoint_dir.join("rpmsg0/src"), "1024\n").unwrap();
+        fs::write(endpoint_dir.join("rpmsg1/src"), "0x401\n").unwrap();
+
+        let resolve = |config: &EndpointConfig| {
+            search_endpoint_path(&sysfs, "rpmsg_ctrl0".to_string(), config).unwrap()
+        };
+        assert_eq!(
+            Path::new(&resolve(&EndpointConfig::new("rpmsg-events", 0x401, 0x1))),
+            sysfs.dev_node("rpmsg1").as_path()
+        );
+        assert_eq!(
+            Path::new(&resolve(&EndpointConfig::new("rpmsg-events", 0x400, 0x1))),
+            sysfs.dev_node("rpmsg0").as_path()
+        );
+        // no src attribute to compare against, the name is enough
+        assert_eq!(
+            Path::new(&resolve(&EndpointConfig::new("rpmsg-bulk", 0x402, 0x2))),
+            sysfs.dev_node("rpmsg2").as_path()
+        );
+    }
+
+    #[test]
+    fn parse_addresses_from_sysfs() {
+        assert_eq!(parse_rpmsg_addr("0x400"), Some(0x400));
+        assert_eq!(parse_rpmsg_addr("1024\n"), Some(1024));
+        assert_eq!(parse_rpmsg_addr("-1"), Some(RPMSG_ADDR_ANY));
+        assert_eq!(parse_rpmsg_addr("rpmsg"), None);
+    }
+
+    #[test]
+    fn endpoint_set_rejects_duplicated_names() {
+        let root = tempfile::tempdir().unwrap();
+        let sysfs = fake_rpmsg_tree(root.path(), DEVICE_NAME, "rpmsg_chrdev");
+        let configs = [
+            EndpointConfig::new("rpmsg-command", 0x400, 0x400),
+            EndpointConfig::new("rpmsg-command", 0x401, 0x401),
+        ];
+        assert!(matches!(
+            RPMsgEndpointSet::open(&sysfs, DEVICE_NAME, "rpmsg_chrdev", &configs),
+            Err(ChannelError::DuplicatedEndpoint { endpoint_name }) if endpoint_name == "rpmsg-command"
+        ));
+    }
+
+    #[test]
+    fn missing_endpoint_is_reported() {
+        let root = tempfile::tempdir().unwrap();
+        let sysfs = fake_rpmsg_tree(root.path(), DEVICE_NAME, "rpmsg_chrdev");
+        fake_endpoint(&sysfs, 0, "other-channel");
+        assert_eq!(
+            search_endpoint_path_by_name(
+                &sysfs,
+                "rpmsg_ctrl0".to_string(),
+                "rpmsg-openamp-demo-channel".to_string()
+            ),
+            Err(ChannelError::FailedToCreateEndpoint {
+                endpoint_name: "rpmsg-openamp-demo-channel".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn wait_for_device_which_shows_up_later() {
+        let root = tempfile::tempdir().unwrap();
+        let sysfs = SysfsRoot::new(root.path());
+        let devices_dir = sysfs.rpmsg_devices_dir();
+        // the devices directory itself doesn't exist yet, like before the rpmsg bus is loaded
+        let announce = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(30));
+            fs::create_dir_all(&devices_dir).unwrap();
+            std::thread::sleep(Duration::from_millis(30));
+            fs::create_dir(devices_dir.join(DEVICE_NAME)).unwrap();
+        });
+        let start = Instant::now();
+        assert_eq!(
+            wait_for_rpmsg_device(&sysfs, DEVICE_NAME, Duration::from_secs(5)),
+            Ok(sysfs.rpmsg_device_dir(DEVICE_NAME))
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+        announce.join().unwrap();
+        // an announced device is found right away
+        assert!(wait_for_rpmsg_device(&sysfs, DEVICE_NAME, Duration::ZERO).is_ok());
+    }
+
+    #[test]
+    fn wait_for_device_times_out() {
+        let root = tempfile::tempdir().unwrap();
+        let sysfs = fake_rpmsg_tree(root.path(), DEVICE_NAME, "rpmsg_chrdev");
+        let timeout = Duration::from_millis(50);
+        let start = Instant::now();
+        assert_eq!(
+            wait_for_rpmsg_device(&sysfs, "virtio0.other-channel.-1.0", timeout),
+            Err(ChannelError::DeviceTimeout {
+                device_name: "virtio0.other-channel.-1.0".to_string(),
+                timeout
+            })
+        );
+        assert!(start.elapsed() >= timeout);
     }
 }
@@ -0,0 +1,141 @@
+//! # Checksum framing
+//! The opt-in framing of an endpoint: every message is followed by the CRC32 (IEEE 802.3,
+//! the zlib one) of the message, as 4 little endian bytes. include/rpmsg_crc32.h has the
+//! same framing in C for the firmware side.
+
+/// the bytes the framing adds to every message
+pub const CHECKSUM_LEN: usize = 4;
+
+pub fn crc32(message: &[u8]) -> u32 {
+    crc32fast::hash(message)
+}
+
+/// message followed by its checksum
+pub fn frame(message: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(message.len() + CHECKSUM_LEN);
+    frame.extend_from_slice(message);
+    frame.extend_from_slice(&crc32(message).to_le_bytes());
+    frame
+}
+
+/// check the checksum at the end of frame and return the size of the message in front of it
+/// on a mismatch the received and the computed checksums are returned,
+/// a frame shorter than a checksum is received as 0
+pub fn verify(frame: &[u8]) -> Result<usize, (u32, u32)> {
+    if frame.len() < CHECKSUM_LEN {
+        return Err((0, crc32(frame)));
+    }
+    let (message, checksum) = frame.split_at(frame.len() - CHECKSUM_LEN);
+    verify_message(message, checksum.try_into().unwrap())
+}
+
+/// same as verify, for a message and its checksum received apart
+pub fn verify_message(message: &[u8], checksum: [u8; CHECKSUM_LEN]) -> Result<usize, (u32, u32)> {
+    let received = u32::from_le_bytes(checksum);
+    let computed = crc32(message);
+    if received == computed {
+        Ok(message.len())
+    } else {
+        Err((received, computed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{AbstractRPMsgChannel, ChannelError};
+    use crate::loopback::LoopbackRPMsgChannel;
+    use crate::MAX_RPMSG_BUFF_SIZE;
+
+    #[test]
+    fn frame_and_verify() {
+        // the check value of CRC-32/ISO-HDLC, rpmsg_crc32.h has to give the same
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        let mut framed = frame(b"scan 42");
+        assert_eq!(framed.len(), 7 + CHECKSUM_LEN);
+        assert_eq!(verify(&framed), Ok(7));
+        assert_eq!(verify(&frame(b"")), Ok(0));
+        framed[0] ^= 0x01;
+        assert!(verify(&framed).is_err());
+        assert_eq!(verify(&[1, 2]), Err((0, crc32(&[1, 2]))));
+    }
+
+    #[test]
+    fn endpoint_framing() {
+        let (mut host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        host.set_checksum(true);
+        host.send(b"image 0x80000000").unwrap();
+        let framed = remote.read(MAX_RPMSG_BUFF_SIZE as usize).unwrap();
+        assert_eq!(framed, frame(b"image 0x80000000"));
+
+        remote.send(&framed).unwrap();
+        assert_eq!(
+            host.read(MAX_RPMSG_BUFF_SIZE as usize).unwrap(),
+            b"image 0x80000000"
+        );
+        let mut corrupted = framed.clone();
+        corrupted[6] = b'1';
+        remote.send(&corrupted).unwrap();
+        assert_eq!(
+            host.read(MAX_RPMSG_BUFF_SIZE as usize),
+            Err(ChannelError::ChecksumMismatch {
+                endpoint_name: "loopback:host".to_string(),
+                received: crc32(b"image 0x80000000"),
+                computed: crc32(b"image 1x80000000"),
+            })
+        );
+        remote.send(&framed).unwrap();
+        let mut buf = [0u8; 32];
+        assert_eq!(host.read_into(&mut buf), Ok(16));
+        assert_eq!(host.stats().checksum_mismatches, 1);
+        assert_eq!(host.stats().messages_received, 3);
+
+        let largest = vec![0u8; MAX_RPMSG_BUFF_SIZE as usize - CHECKSUM_LEN];
+        host.send(&largest).unwrap();
+        assert!(host.send(&[0u8; MAX_RPMSG_BUFF_SIZE as usize]).is_err());
+    }
+
+    #[test]
+    fn read_into_leaves_room_for_the_checksum() {
+        let (mut host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        host.set_checksum(true);
+        remote.set_checksum(true);
+        remote.send(b"image 0x80000000").unwrap();
+        let mut buf = [0u8; 12];
+        assert_eq!(
+            host.read_into(&mut buf),
+            Err(ChannelError::MessageBufferOverflow {
+                capacity: 12,
+                message_size: 16,
+            })
+        );
+        // the checksum doesn't need room in buf
+        remote.send(b"image 0x80000000").unwrap();
+        let mut buf = [0u8; 16];
+        assert_eq!(host.read_into(&mut buf), Ok(16));
+        assert_eq!(&buf, b"image 0x80000000");
+        remote.send(b"image 0x").unwrap();
+        assert_eq!(host.read_into(&mut buf[..10]), Ok(8));
+        assert_eq!(&buf[..8], b"image 0x");
+
+        let mut corrupted = frame(b"image 0x80000000");
+        corrupted[6] = b'1';
+        remote.set_checksum(false);
+        remote.send(&corrupted).unwrap();
+        assert_eq!(
+            host.read_into(&mut buf),
+            Err(ChannelError::ChecksumMismatch {
+                endpoint_name: "loopback:host".to_string(),
+                received: crc32(b"image 0x80000000"),
+                computed: crc32(b"image 1x80000000"),
+            })
+        );
+        remote.send(b"ab").unwrap();
+        assert!(matches!(
+            host.read_into(&mut buf[..1]),
+            Err(ChannelError::ChecksumMismatch { received: 0, .. })
+        ));
+        assert_eq!(host.stats().overflows, 1);
+        assert_eq!(host.stats().checksum_mismatches, 2);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::channel::{parse_rpmsg_addr, read_sysfs_attribute, ChannelError};
+use crate::sysfs::SysfsRoot;
+use log::warn;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+/// # Rpmsg device name
+/// The name the kernel gives to an rpmsg device, `{virtio_id}.{channel_name}.{src}.{dst}`.
+/// The addresses are printed as signed decimals, so RPMSG_ADDR_ANY shows up as -1,
+/// e.g. `virtio0.rpmsg-openamp-demo-channel.-1.0` is the demo channel with src any and dst 0.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RPMsgDeviceName {
+    pub virtio_id: String,
+    pub channel_name: String,
+    pub src: u32,
+    pub dst: u32,
+}
+
+impl RPMsgDeviceName {
+    pub fn new(virtio_id: &str, channel_name: &str, src: u32, dst: u32) -> Self {
+        RPMsgDeviceName {
+            virtio_id: virtio_id.to_string(),
+            channel_name: channel_name.to_string(),
+            src,
+            dst,
+        }
+    }
+
+    /// the name written as `{virtio_id}.{channel_name}.-{version}`, the version "1.0"
+    /// stands for src -1 (any) and dst 0
+    pub fn with_version(
+        virtio_id: &str,
+        channel_name: &str,
+        version: &str,
+    ) -> Result<Self, ChannelError> {
+        format!("{}.{}.-{}", virtio_id, channel_name, version).parse()
+    }
+}
+
+impl FromStr for RPMsgDeviceName {
+    type Err = ChannelError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let invalid = || ChannelError::InvalidDeviceName {
+            name: name.to_string(),
+        };
+        // why: the channel name may contain dots, the addresses and the virtio id can't
+        let mut from_right = name.rsplitn(3, '.');
+        let dst = from_right.next().ok_or_else(invalid)?;
+        let src = from_right.next().ok_or_else(invalid)?;
+        let (virtio_id, channel_name) = from_right
+            .next()
+            .and_then(|rest| rest.split_once('.'))
+            .ok_or_else(invalid)?;
+        if virtio_id.is_empty() || channel_name.is_empty() {
+            return Err(invalid());
+        }
+        Ok(RPMsgDeviceName {
+            virtio_id: virtio_id.to_string(),
+            channel_name: channel_name.to_string(),
+            src: parse_device_addr(src).ok_or_else(invalid)?,
+            dst: parse_device_addr(dst).ok_or_else(invalid)?,
+        })
+    }
+}
+
+impl fmt::Display for RPMsgDeviceName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.virtio_id, self.channel_name, self.src as i32, self.dst as i32
+        )
+    }
+}
+
+// the kernel prints the addresses with %d
+fn parse_device_addr(addr: &str) -> Option<u32> {
+    addr.parse::<i32>().ok().map(|addr| addr as u32)
+}
+
+/// an rpmsg device found on the bus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RPMsgDeviceInfo {
+    pub name: RPMsgDeviceName,
+    /// local address, from the src attribute when the kernel exposes it
+    pub src: u32,
+    /// remote address, from the dst attribute when the kernel exposes it
+    pub dst: u32,
+    /// the driver bound to the device, None when no driver is bound
+    pub driver: Option<String>,
+    /// e.g. `rpmsg:rpmsg-openamp-demo-channel`
+    pub modalias: Option<String>,
+}
+
+/// list the devices announced on the rpmsg bus, sorted by name
+/// an empty list is returned when the rpmsg bus is not loaded,
+/// an entry which can't be read or isn't an rpmsg device name is skipped with a warning
+pub fn list_rpmsg_devices(sysfs: &SysfsRoot) -> Result<Vec<RPMsgDeviceInfo>, ChannelError> {
+    let devices_dir = sysfs.rpmsg_devices_dir();
+    if !devices_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let entries = fs::read_dir(&devices_dir).map_err(|_| ChannelError::FailedToReadDir {
+        dir_path: devices_dir.display().to_string(),
+    })?;
+    let mut devices = Vec::new();
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("skipping an entry of {}: {}", devices_dir.display(), e);
+                continue;
+            }
+        };
+        let file_name = entry.file_name();
+        let file_name = match file_name.to_str() {
+            Some(file_name) => file_name,
+            None => {
+                warn!("skipping rpmsg device {:?}: not UTF-8", file_name);
+                continue;
+            }
+        };
+        let name: RPMsgDeviceName = match file_name.parse() {
+            Ok(name) => name,
+            Err(e) => {
+                warn!("skipping rpmsg device: {}", e);
+                continue;
+            }
+        };
+        let device_dir = entry.path();
+        let addr = |attribute: &str| {
+            read_sysfs_attribute(&device_dir.join(attribute))
+                .ok()
+                .and_then(|addr| parse_rpmsg_addr(&addr))
+        };
+        let driver = fs::read_link(device_dir.join("driver"))
+            .ok()
+            .and_then(|driver| Some(driver.file_name()?.to_str()?.to_string()));
+        devices.push(RPMsgDeviceInfo {
+            src: addr("src").unwrap_or(name.src),
+            dst: addr("dst").unwrap_or(name.dst),
+            driver,
+            modalias: read_sysfs_attribute(&device_dir.join("modalias")).ok(),
+            name,
+        });
+    }
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::tests::fake_rpmsg_tree;
+    use crate::RPMSG_ADDR_ANY;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn parse_and_format_names() {
+        let name: RPMsgDeviceName = "virtio0.rpmsg-openamp-demo-channel.-1.0".parse().unwrap();
+        assert_eq!(
+            name,
+            RPMsgDeviceName::new("virtio0", "rpmsg-openamp-demo-channel", RPMSG_ADDR_ANY, 0)
+        );
+        assert_eq!(
+            RPMsgDeviceName::with_version("virtio0", "rpmsg-openamp-demo-channel", "1.0"),
+            Ok(name)
+        );
+        for raw in [
+            "virtio0.rpmsg-openamp-demo-channel.-1.0",
+            "virtio1.rpmsg-raw.1024.-1",
+            "virtio0.rpmsg.with.dots.1024.53",
+        ] {
+            assert_eq!(raw.parse::<RPMsgDeviceName>().unwrap().to_string(), raw);
+        }
+        let name: RPMsgDeviceName = "virtio0.rpmsg.with.dots.1024.53".parse().unwrap();
+        assert_eq!(name.channel_name, "rpmsg.with.dots");
+        assert_eq!((name.src, name.dst), (1024, 53));
+        for invalid in [
+            "virtio0.-1.0",
+            "rpmsg_ctrl0",
+            "virtio0.channel.any.0",
+            ".channel.1.0",
+        ] {
+            assert_eq!(
+                invalid.parse::<RPMsgDeviceName>(),
+                Err(ChannelError::InvalidDeviceName {
+                    name: invalid.to_string()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn list_devices_on_the_bus() {
+        let root = tempfile::tempdir().unwrap();
+        let sysfs = fake_rpmsg_tree(
+            root.path(),
+            "virtio0.rpmsg-openamp-demo-channel.-1.0",
+            "rpmsg_chrdev",
+        );
+        let demo_dir = sysfs.rpmsg_device_dir("virtio0.rpmsg-openamp-demo-channel.-1.0");
+        fs::write(demo_dir.join("src"), "0xffffffff\n").unwrap();
+        fs::write(demo_dir.join("dst"), "0x0\n").unwrap();
+        fs::write(
+            demo_dir.join("modalias"),
+            "rpmsg:rpmsg-openamp-demo-channel\n",
+        )
+        .unwrap();
+        symlink(
+            sysfs.rpmsg_driver_dir("rpmsg_chrdev"),
+            demo_dir.join("driver"),
+        )
+        .unwrap();
+        fs::create_dir(sysfs.rpmsg_device_dir("virtio0.rpmsg-raw.1024.1025")).unwrap();
+        fs::create_dir(sysfs.rpmsg_device_dir("not-an-rpmsg-device")).unwrap();
+        fs::create_dir(
+            sysfs
+                .rpmsg_devices_dir()
+                .join(OsStr::from_bytes(b"virtio0.\xff.1.2")),
+        )
+        .unwrap();
+
+        let devices = list_rpmsg_devices(&sysfs).unwrap();
+        assert_eq!(
+            devices,
+            vec![
+                RPMsgDeviceInfo {
+                    name: "virtio0.rpmsg-openamp-demo-channel.-1.0".parse().unwrap(),
+                    src: RPMSG_ADDR_ANY,
+                    dst: 0,
+                    driver: Some("rpmsg_chrdev".to_string()),
+                    modalias: Some("rpmsg:rpmsg-openamp-demo-channel".to_string()),
+                },
+                RPMsgDeviceInfo {
+                    name: "virtio0.rpmsg-raw.1024.1025".parse().unwrap(),
+                    src: 1024,
+                    dst: 1025,
+                    driver: None,
+                    modalias: None,
+                },
+            ]
+        );
+        assert_eq!(
+            list_rpmsg_devices(&SysfsRoot::new(root.path().join("empty"))),
+            Ok(Vec::new())
+        );
+    }
+}
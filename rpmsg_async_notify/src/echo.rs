@@ -0,0 +1,301 @@
+use crate::channel::{AbstractRPMsgChannel, ChannelError};
+use crate::{Payload, MAX_RPMSG_BUFF_SIZE};
+use bincode::deserialize;
+use log::{trace, warn};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use std::os::unix::prelude::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, sleep, JoinHandle};
+use std::time::Duration;
+
+/// how often run checks whether it was asked to stop
+const ECHO_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// how the responder treats every payload
+#[derive(Debug, Clone, PartialEq)]
+pub struct EchoConfig {
+    /// time spent before a payload is echoed
+    pub delay: Duration,
+    /// up to this much is added to the delay, picked at random for every payload
+    pub jitter: Duration,
+    /// share of the payloads which are not echoed, between 0 and 1
+    pub drop_rate: f64,
+    /// seed of the random numbers, so a run can be repeated
+    pub seed: u64,
+}
+
+impl Default for EchoConfig {
+    fn default() -> Self {
+        EchoConfig {
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            drop_rate: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+/// what the responder did so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EchoStats {
+    pub received: usize,
+    pub echoed: usize,
+    pub dropped: usize,
+    /// messages which are not a Payload, too large or corrupted, they are never echoed
+    pub invalid: usize,
+}
+
+/// # Echo responder
+/// The remote side of the benchmarks without the echo_test firmware.
+/// Every Payload read from the channel is sent back unchanged, after the configured delay,
+/// unless it is picked to be dropped. The channel has to be non-blocking.
+pub struct EchoResponder<C>
+where
+    C: AbstractRPMsgChannel + AsRawFd,
+{
+    channel: C,
+    config: EchoConfig,
+    rng: fastrand::Rng,
+    stats: EchoStats,
+    // reused for every message, so serving doesn't allocate
+    buffer: Vec<u8>,
+}
+
+impl<C> EchoResponder<C>
+where
+    C: AbstractRPMsgChannel + AsRawFd,
+{
+    pub fn new(channel: C, config: EchoConfig) -> Self {
+        EchoResponder {
+            channel,
+            rng: fastrand::Rng::with_seed(config.seed),
+            config,
+            stats: EchoStats::default(),
+            buffer: vec![0; MAX_RPMSG_BUFF_SIZE as usize],
+        }
+    }
+
+    /// handle one message read from the channel
+    pub fn respond(&mut self, message: &[u8]) -> Result<(), ChannelError> {
+        self.stats.received += 1;
+        let payload: Payload = match deserialize(message) {
+            Ok(payload) => payload,
+            Err(e) => {
+                warn!("not echoing a message which is not a payload: {:?}", e);
+                self.stats.invalid += 1;
+                return Ok(());
+            }
+        };
+        if self.config.drop_rate > 0.0 && self.rng.f64() < self.config.drop_rate {
+            trace!("dropping payload {}", payload.num);
+            self.stats.dropped += 1;
+            return Ok(());
+        }
+        let delay = self.config.delay + self.jitter();
+        if !delay.is_zero() {
+            sleep(delay);
+        }
+        self.channel.send(message)?;
+        trace!("echoed payload {}", payload.num);
+        self.stats.echoed += 1;
+        Ok(())
+    }
+
+    /// wait up to timeout for messages and handle every pending one
+    /// return false once the peer closed the channel, which reads as an empty message
+    pub fn serve_once(&mut self, timeout: Duration) -> Result<bool, ChannelError> {
+        let mut fds = [PollFd::new(self.channel.as_raw_fd(), PollFlags::POLLIN)];
+        let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        match poll(&mut fds, timeout_ms) {
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let result = loop {
+            match self.channel.read_into(&mut buffer) {
+                Ok(0) => break Ok(false),
+                Ok(size) => {
+                    if let Err(e) = self.respond(&buffer[..size]) {
+                        break Err(e);
+                    }
+                }
+                Err(ChannelError::WouldBlock { .. }) => break Ok(true),
+                // why: the firmware drops a bad message and keeps echoing the next ones
+                Err(e) if e.is_bad_message() => {
+                    warn!("not echoing a bad message: {:?}", e);
+                    self.stats.received += 1;
+                    self.stats.invalid += 1;
+                }
+                Err(e) => break Err(e),
+            }
+        };
+        self.buffer = buffer;
+        result
+    }
+
+    /// serve until stop is set or the peer closed the channel
+    pub fn run(&mut self, stop: &AtomicBool) -> Result<(), ChannelError> {
+        while !stop.load(Ordering::SeqCst) {
+            if !self.serve_once(ECHO_POLL_INTERVAL)? {
+                trace!("peer closed the channel");
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> EchoStats {
+        self.stats
+    }
+
+    pub fn get_ref(&self) -> &C {
+        &self.channel
+    }
+
+    pub fn into_inner(self) -> C {
+        self.channel
+    }
+
+    fn jitter(&mut self) -> Duration {
+        let jitter = self.config.jitter.as_nanos() as u64;
+        if jitter == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_nanos(self.rng.u64(0..=jitter))
+        }
+    }
+}
+
+impl<C> EchoResponder<C>
+where
+    C: AbstractRPMsgChannel + AsRawFd + Send + 'static,
+{
+    /// serve on a thread of its own until the handle is stopped
+    pub fn spawn(mut self) -> EchoHandle {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            let result = self.run(&thread_stop);
+            if let Err(e) = &result {
+                warn!("echo responder stopped with error: {}", e);
+            }
+            result.map(|_| self.stats())
+        });
+        EchoHandle {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+/// controls a responder started by spawn, the responder is stopped on drop
+pub struct EchoHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<EchoStats, ChannelError>>>,
+}
+
+impl EchoHandle {
+    /// stop the responder and return what it did, or the error it stopped with
+    pub fn stop(mut self) -> Result<EchoStats, ChannelError> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<EchoStats, ChannelError> {
+        self.stop.store(true, Ordering::SeqCst);
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or_else(|_| {
+                Err(ChannelError::IOError {
+                    error: "echo thread panicked".to_string(),
+                })
+            }),
+            None => Ok(EchoStats::default()),
+        }
+    }
+}
+
+impl Drop for EchoHandle {
+    fn drop(&mut self) {
+        let _ = self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::LoopbackRPMsgChannel;
+    use bincode::serialize;
+    use std::time::Instant;
+
+    fn wait_message(channel: &mut LoopbackRPMsgChannel, timeout: Duration) -> Option<Vec<u8>> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Ok(message) = channel.read(MAX_RPMSG_BUFF_SIZE as usize) {
+                return Some(message);
+            }
+            sleep(Duration::from_millis(1));
+        }
+        None
+    }
+
+    #[test]
+    fn echoes_payloads() {
+        let (mut host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let echo = EchoResponder::new(remote, EchoConfig::default()).spawn();
+        for id in 0..5 {
+            let message = serialize(&Payload::new(id)).unwrap();
+            host.send(&message).unwrap();
+            assert_eq!(
+                wait_message(&mut host, Duration::from_secs(5)),
+                Some(message)
+            );
+        }
+        host.send(b"garbage").unwrap();
+        let stats = echo.stop().unwrap();
+        assert_eq!(stats.echoed, 5);
+        assert_eq!(stats.received, stats.echoed + stats.invalid);
+    }
+
+    #[test]
+    fn bad_message_is_skipped() {
+        let (mut host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        remote.set_checksum(true);
+        let echo = EchoResponder::new(remote, EchoConfig::default()).spawn();
+        host.send(b"no checksum").unwrap();
+        host.set_checksum(true);
+        let message = serialize(&Payload::new(1)).unwrap();
+        host.send(&message).unwrap();
+        assert_eq!(
+            wait_message(&mut host, Duration::from_secs(5)),
+            Some(message)
+        );
+        let stats = echo.stop().unwrap();
+        assert_eq!(stats.echoed, 1);
+        assert_eq!(stats.invalid, 1);
+    }
+
+    #[test]
+    fn delay_and_drop() {
+        let (mut host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let config = EchoConfig {
+            delay: Duration::from_millis(20),
+            ..EchoConfig::default()
+        };
+        let mut echo = EchoResponder::new(remote, config);
+        let start = Instant::now();
+        echo.respond(&serialize(&Payload::new(1)).unwrap()).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert!(host.read(MAX_RPMSG_BUFF_SIZE as usize).is_ok());
+
+        let (mut host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let config = EchoConfig {
+            drop_rate: 1.0,
+            ..EchoConfig::default()
+        };
+        let mut echo = EchoResponder::new(remote, config);
+        echo.respond(&serialize(&Payload::new(1)).unwrap()).unwrap();
+        assert_eq!(echo.stats().dropped, 1);
+        assert!(host.read(MAX_RPMSG_BUFF_SIZE as usize).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+use sha2::{Digest, Sha256};
+use snafu::Snafu;
+
+/// the section remoteproc reads the resources of the firmware from
+pub const RESOURCE_TABLE_SECTION: &str = ".resource_table";
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_ARM: u16 = 40;
+const ELF32_HEADER_LEN: usize = 52;
+const ELF32_SECTION_HEADER_LEN: usize = 40;
+
+#[derive(Debug, Snafu, Clone, PartialEq)]
+#[snafu(visibility = "pub")]
+pub enum ElfError {
+    #[snafu(display("not an ELF file"))]
+    NotElf {},
+    #[snafu(display("unsupported ELF, {}", reason))]
+    UnsupportedElf { reason: String },
+    #[snafu(display("truncated ELF, {} is out of the file", what))]
+    TruncatedElf { what: String },
+    #[snafu(display("no {} section", name))]
+    MissingSection { name: String },
+}
+
+/// a section of an ELF file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfSection {
+    pub name: String,
+    /// the address the section is loaded at on the remote processor
+    pub addr: u32,
+    /// where the data of the section starts in the file
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// # Firmware image
+/// A 32-bit little endian ELF file, the format remoteproc loads on the R5 cores.
+/// Only the parts needed to check a firmware are parsed: the header and the sections.
+#[derive(Debug, Clone)]
+pub struct FirmwareImage<'a> {
+    data: &'a [u8],
+    elf_type: u16,
+    machine: u16,
+    sections: Vec<ElfSection>,
+}
+
+impl<'a> FirmwareImage<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < ELF32_HEADER_LEN || &data[..4] != ELF_MAGIC {
+            return Err(ElfError::NotElf {});
+        }
+        if data[4] != ELFCLASS32 {
+            return Err(unsupported("not a 32-bit ELF"));
+        }
+        if data[5] != ELFDATA2LSB {
+            return Err(unsupported("not little endian"));
+        }
+        let section_table = read_u32(data, 32) as usize;
+        let section_header_len = read_u16(data, 46) as usize;
+        let section_count = read_u16(data, 48) as usize;
+        let names_index = read_u16(data, 50) as usize;
+        let mut image = FirmwareImage {
+            data,
+            elf_type: read_u16(data, 16),
+            machine: read_u16(data, 18),
+            sections: Vec::new(),
+        };
+        if section_count == 0 {
+            return Ok(image);
+        }
+        if section_header_len < ELF32_SECTION_HEADER_LEN {
+            return Err(unsupported("section headers are too small"));
+        }
+        if names_index >= section_count {
+            return Err(unsupported("no section names"));
+        }
+        // (name offset, addr, offset, size) of every section header
+        let mut headers = Vec::with_capacity(section_count);
+        for index in 0..section_count {
+            let start = section_table + index * section_header_len;
+            let header = data
+                .get(start..start + ELF32_SECTION_HEADER_LEN)
+                .ok_or_else(|| truncated(&format!("section header {}", index)))?;
+            headers.push((
+                read_u32(header, 0) as usize,
+                read_u32(header, 12),
+                read_u32(header, 16),
+                read_u32(header, 20),
+            ));
+        }
+        let (_, _, names_offset, names_size) = headers[names_index];
+        let names = data
+            .get(names_offset as usize..(names_offset as usize + names_size as usize))
+            .ok_or_else(|| truncated("the section names"))?;
+        for (name, addr, offset, size) in headers {
+            let name = names
+                .get(name..)
+                .and_then(|name| name.split(|byte| *byte == 0).next())
+                .ok_or_else(|| truncated("a section name"))?;
+            image.sections.push(ElfSection {
+                name: String::from_utf8_lossy(name).into_owned(),
+                addr,
+                offset,
+                size,
+            });
+        }
+        Ok(image)
+    }
+
+    /// check the image is a firmware remoteproc can boot on an R5 core:
+    /// an ARM executable with a resource table
+    pub fn validate(&self) -> Result<(), ElfError> {
+        if self.machine != EM_ARM {
+            return Err(ElfError::UnsupportedElf {
+                reason: format!("machine {} is not ARM", self.machine),
+            });
+        }
+        if self.elf_type != ET_EXEC {
+            return Err(ElfError::UnsupportedElf {
+                reason: format!("type {} is not an executable", self.elf_type),
+            });
+        }
+        self.section_data(RESOURCE_TABLE_SECTION)?;
+        Ok(())
+    }
+
+    pub fn sections(&self) -> &[ElfSection] {
+        &self.sections
+    }
+
+    pub fn section(&self, name: &str) -> Option<&ElfSection> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    /// the content of the section called name
+    pub fn section_data(&self, name: &str) -> Result<&'a [u8], ElfError> {
+        let section = self.section(name).ok_or_else(|| ElfError::MissingSection {
+            name: name.to_string(),
+        })?;
+        let start = section.offset as usize;
+        self.data
+            .get(start..start + section.size as usize)
+            .ok_or_else(|| truncated(&format!("section {}", name)))
+    }
+}
+
+/// the SHA-256 of data, in lowercase hex like sha256sum prints it
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn unsupported(reason: &str) -> ElfError {
+    ElfError::UnsupportedElf {
+        reason: reason.to_string(),
+    }
+}
+
+fn truncated(what: &str) -> ElfError {
+    ElfError::TruncatedElf {
+        what: what.to_string(),
+    }
+}
+
+// the callers checked the bytes are in data
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// build an ELF32 ARM executable with the sections, in the order they are given
+    pub(crate) fn fake_firmware(sections: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut elf = vec![0u8; ELF32_HEADER_LEN];
+        elf[..4].copy_from_slice(ELF_MAGIC);
+        elf[4] = ELFCLASS32;
+        elf[5] = ELFDATA2LSB;
+        elf[6] = 1;
+        elf[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+        elf[18..20].copy_from_slice(&EM_ARM.to_le_bytes());
+        elf[20..24].copy_from_slice(&1u32.to_le_bytes());
+        elf[40..42].copy_from_slice(&(ELF32_HEADER_LEN as u16).to_le_bytes());
+
+        let mut names = vec![0u8];
+        // (name offset, file offset, size) of the null section, the sections and the names
+        let mut headers = vec![(0, 0, 0)];
+        for (name, data) in sections {
+            headers.push((names.len(), elf.len(), data.len()));
+            names.extend_from_slice(name.as_bytes());
+            names.push(0);
+            elf.extend_from_slice(data);
+        }
+        headers.push((names.len(), elf.len(), 0));
+        names.extend_from_slice(b".shstrtab\0");
+        let last = headers.len() - 1;
+        headers[last].2 = names.len();
+        elf.extend_from_slice(&names);
+
+        let section_table = elf.len();
+        for (index, (name, offset, size)) in headers.iter().enumerate() {
+            let mut header = [0u8; ELF32_SECTION_HEADER_LEN];
+            header[0..4].copy_from_slice(&(*name as u32).to_le_bytes());
+            // SHT_PROGBITS, SHT_STRTAB for the names
+            let section_type: u32 = if index == last { 3 } else { 1 };
+            header[4..8].copy_from_slice(&section_type.to_le_bytes());
+            header[12..16].copy_from_slice(&(0x3ed0_0000 + *offset as u32).to_le_bytes());
+            header[16..20].copy_from_slice(&(*offset as u32).to_le_bytes());
+            header[20..24].copy_from_slice(&(*size as u32).to_le_bytes());
+            elf.extend_from_slice(&header);
+        }
+        elf[32..36].copy_from_slice(&(section_table as u32).to_le_bytes());
+        elf[46..48].copy_from_slice(&(ELF32_SECTION_HEADER_LEN as u16).to_le_bytes());
+        elf[48..50].copy_from_slice(&(headers.len() as u16).to_le_bytes());
+        elf[50..52].copy_from_slice(&(last as u16).to_le_bytes());
+        elf
+    }
+
+    #[test]
+    fn validate_firmware() {
+        let elf = fake_firmware(&[(".text", &[0xfe; 16]), (RESOURCE_TABLE_SECTION, &[1, 2, 3])]);
+        let image = FirmwareImage::parse(&elf).unwrap();
+        image.validate().unwrap();
+        assert_eq!(
+            image
+                .sections()
+                .iter()
+                .map(|section| section.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["", ".text", RESOURCE_TABLE_SECTION, ".shstrtab"]
+        );
+        assert_eq!(
+            image.section_data(RESOURCE_TABLE_SECTION),
+            Ok(&[1u8, 2, 3][..])
+        );
+
+        let no_table = fake_firmware(&[(".text", &[0xfe; 16])]);
+        assert_eq!(
+            FirmwareImage::parse(&no_table).unwrap().validate(),
+            Err(ElfError::MissingSection {
+                name: RESOURCE_TABLE_SECTION.to_string()
+            })
+        );
+        let mut x86 = elf.clone();
+        x86[18] = 62;
+        assert!(matches!(
+            FirmwareImage::parse(&x86).unwrap().validate(),
+            Err(ElfError::UnsupportedElf { .. })
+        ));
+        let mut relocatable = elf.clone();
+        relocatable[16] = 1;
+        assert!(FirmwareImage::parse(&relocatable)
+            .unwrap()
+            .validate()
+            .is_err());
+        let mut elf64 = elf.clone();
+        elf64[4] = 2;
+        assert!(FirmwareImage::parse(&elf64).is_err());
+        assert_eq!(
+            FirmwareImage::parse(b"#!/bin/sh\n").unwrap_err(),
+            ElfError::NotElf {}
+        );
+        assert!(matches!(
+            FirmwareImage::parse(&elf[..elf.len() - 8]),
+            Err(ElfError::TruncatedElf { .. })
+        ));
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,615 @@
+use crate::channel::{AbstractRPMsgChannel, ChannelError};
+use crate::MAX_RPMSG_BUFF_SIZE;
+use log::{trace, warn};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// size of FragmentHeader on the wire
+pub const FRAGMENT_HEADER_LEN: usize = 8;
+/// the largest chunk of a message carried by one fragment, on a channel without framing
+pub const FRAGMENT_PAYLOAD_MAX_SIZE: usize = MAX_RPMSG_BUFF_SIZE as usize - FRAGMENT_HEADER_LEN;
+/// how long the fragments of a message are kept before it is reported as incomplete
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_millis(500);
+/// the largest message the reassembler accepts unless told otherwise
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024;
+/// how many messages may wait for more fragments at once unless told otherwise
+pub const DEFAULT_MAX_PENDING_MESSAGES: usize = 8;
+
+/// header in front of every fragment, all fields are little endian
+/// the layout matches `struct rpmsg_fragment_header` in include/rpmsg_fragment.h
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct FragmentHeader {
+    /// shared by all fragments of a message, incremented per message
+    pub message_id: u16,
+    /// position of the fragment in the message, starting from 0
+    pub fragment_index: u16,
+    /// number of fragments of the message, at least 1
+    pub fragment_count: u16,
+    /// number of payload bytes following the header
+    pub payload_len: u16,
+}
+
+impl FragmentHeader {
+    pub fn to_bytes(&self) -> [u8; FRAGMENT_HEADER_LEN] {
+        let mut bytes = [0u8; FRAGMENT_HEADER_LEN];
+        bytes[0..2].copy_from_slice(&self.message_id.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.fragment_index.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.fragment_count.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.payload_len.to_le_bytes());
+        bytes
+    }
+
+    /// parse the header in front of fragment and check it against the size of the fragment
+    pub fn parse(fragment: &[u8]) -> Result<FragmentHeader, ChannelError> {
+        if fragment.len() < FRAGMENT_HEADER_LEN {
+            return Err(ChannelError::InvalidFragment {
+                reason: format!("{} bytes is shorter than the header", fragment.len()),
+            });
+        }
+        let field = |i: usize| u16::from_le_bytes([fragment[i], fragment[i + 1]]);
+        let header = FragmentHeader {
+            message_id: field(0),
+            fragment_index: field(2),
+            fragment_count: field(4),
+            payload_len: field(6),
+        };
+        if header.fragment_index >= header.fragment_count {
+            return Err(ChannelError::InvalidFragment {
+                reason: format!(
+                    "index {} out of {} fragments",
+                    header.fragment_index, header.fragment_count
+                ),
+            });
+        }
+        if header.payload_len as usize != fragment.len() - FRAGMENT_HEADER_LEN {
+            return Err(ChannelError::InvalidFragment {
+                reason: format!(
+                    "header claims {} bytes of payload, got {}",
+                    header.payload_len,
+                    fragment.len() - FRAGMENT_HEADER_LEN
+                ),
+            });
+        }
+        Ok(header)
+    }
+}
+
+/// split messages into fragments which fit in one rpmsg buffer
+pub struct Fragmenter {
+    next_message_id: u16,
+    fragment_payload_size: usize,
+}
+
+impl Default for Fragmenter {
+    fn default() -> Self {
+        Fragmenter {
+            next_message_id: 0,
+            fragment_payload_size: FRAGMENT_PAYLOAD_MAX_SIZE,
+        }
+    }
+}
+
+impl Fragmenter {
+    /// fragment_payload_size is the number of message bytes put in each fragment, 1 to 65535
+    pub fn new(fragment_payload_size: usize) -> Result<Self, ChannelError> {
+        check_fragment_payload_size(fragment_payload_size)?;
+        Ok(Fragmenter {
+            next_message_id: 0,
+            fragment_payload_size,
+        })
+    }
+
+    /// the number of message bytes put in each fragment
+    pub fn fragment_payload_size(&self) -> usize {
+        self.fragment_payload_size
+    }
+
+    /// change the number of message bytes put in each fragment of the next messages
+    pub fn set_fragment_payload_size(
+        &mut self,
+        fragment_payload_size: usize,
+    ) -> Result<(), ChannelError> {
+        check_fragment_payload_size(fragment_payload_size)?;
+        self.fragment_payload_size = fragment_payload_size;
+        Ok(())
+    }
+
+    /// the largest message split accepts
+    pub fn max_message_size(&self) -> usize {
+        self.fragment_payload_size * u16::MAX as usize
+    }
+
+    /// split message into fragments, an empty message is sent as one empty fragment
+    pub fn split(&mut self, message: &[u8]) -> Result<Vec<Vec<u8>>, ChannelError> {
+        if message.len() > self.max_message_size() {
+            return Err(ChannelError::MessageTooLarge {
+                message_size: message.len(),
+                max_size: self.max_message_size(),
+            });
+        }
+        let message_id = self.next_message_id;
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+
+        let chunks: Vec<&[u8]> = if message.is_empty() {
+            vec![message]
+        } else {
+            message.chunks(self.fragment_payload_size).collect()
+        };
+        let fragment_count = chunks.len() as u16;
+        Ok(chunks
+            .into_iter()
+            .enumerate()
+            .map(|(fragment_index, chunk)| {
+                let header = FragmentHeader {
+                    message_id,
+                    fragment_index: fragment_index as u16,
+                    fragment_count,
+                    payload_len: chunk.len() as u16,
+                };
+                let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_LEN + chunk.len());
+                fragment.extend_from_slice(&header.to_bytes());
+                fragment.extend_from_slice(chunk);
+                fragment
+            })
+            .collect())
+    }
+}
+
+// the fragments received so far for one message
+struct PartialMessage {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    first_seen: Instant,
+}
+
+impl PartialMessage {
+    fn missing(&self) -> Vec<u16> {
+        self.fragments
+            .iter()
+            .enumerate()
+            .filter(|(_, fragment)| fragment.is_none())
+            .map(|(index, _)| index as u16)
+            .collect()
+    }
+}
+
+/// put fragments back together into messages
+/// the remote picks the number of fragments of a message, so the reassembler bounds
+/// both the fragments of a message and the messages waiting for fragments
+pub struct Reassembler {
+    timeout: Duration,
+    max_fragment_count: usize,
+    max_pending: usize,
+    partial: HashMap<u16, PartialMessage>,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Reassembler::new(DEFAULT_REASSEMBLY_TIMEOUT)
+    }
+}
+
+impl Reassembler {
+    /// a message not completed within timeout after its first fragment is dropped
+    /// messages up to DEFAULT_MAX_MESSAGE_SIZE in fragments of FRAGMENT_PAYLOAD_MAX_SIZE
+    /// are accepted, DEFAULT_MAX_PENDING_MESSAGES at once
+    pub fn new(timeout: Duration) -> Self {
+        Reassembler {
+            timeout,
+            max_fragment_count: DEFAULT_MAX_MESSAGE_SIZE.div_ceil(FRAGMENT_PAYLOAD_MAX_SIZE),
+            max_pending: DEFAULT_MAX_PENDING_MESSAGES,
+            partial: HashMap::new(),
+        }
+    }
+
+    /// accept messages up to max_message_size, sent in fragments of fragment_payload_size
+    /// a message announcing more fragments is refused before anything is allocated
+    pub fn set_max_message_size(
+        &mut self,
+        max_message_size: usize,
+        fragment_payload_size: usize,
+    ) -> Result<(), ChannelError> {
+        check_fragment_payload_size(fragment_payload_size)?;
+        self.max_fragment_count = max_message_size
+            .div_ceil(fragment_payload_size)
+            .clamp(1, u16::MAX as usize);
+        Ok(())
+    }
+
+    /// the number of messages which may wait for more fragments at once,
+    /// the fragments of another message are refused until one completes or expires
+    pub fn set_max_pending(&mut self, max_pending: usize) {
+        self.max_pending = max_pending;
+    }
+
+    /// number of messages waiting for more fragments
+    pub fn pending(&self) -> usize {
+        self.partial.len()
+    }
+
+    /// add a fragment received at now, return the message once all its fragments arrived
+    pub fn push(&mut self, fragment: &[u8], now: Instant) -> Result<Option<Vec<u8>>, ChannelError> {
+        let header = FragmentHeader::parse(fragment)?;
+        let payload = &fragment[FRAGMENT_HEADER_LEN..];
+        if header.fragment_count == 1 {
+            return Ok(Some(payload.to_vec()));
+        }
+        if header.fragment_count as usize > self.max_fragment_count {
+            return Err(ChannelError::InvalidFragment {
+                reason: format!(
+                    "message {} claims {} fragments, at most {} are accepted",
+                    header.message_id, header.fragment_count, self.max_fragment_count
+                ),
+            });
+        }
+        if self.partial.len() >= self.max_pending && !self.partial.contains_key(&header.message_id)
+        {
+            return Err(ChannelError::InvalidFragment {
+                reason: format!(
+                    "message {} doesn't fit, {} messages already wait for fragments",
+                    header.message_id,
+                    self.partial.len()
+                ),
+            });
+        }
+
+        let partial = self
+            .partial
+            .entry(header.message_id)
+            .or_insert_with(|| PartialMessage {
+                fragments: vec![None; header.fragment_count as usize],
+                received: 0,
+                first_seen: now,
+            });
+        if partial.fragments.len() != header.fragment_count as usize {
+            return Err(ChannelError::InvalidFragment {
+                reason: format!(
+                    "message {} has {} fragments, fragment {} claims {}",
+                    header.message_id,
+                    partial.fragments.len(),
+                    header.fragment_index,
+                    header.fragment_count
+                ),
+            });
+        }
+        let slot = &mut partial.fragments[header.fragment_index as usize];
+        if slot.is_some() {
+            return Err(ChannelError::DuplicatedFragment {
+                message_id: header.message_id,
+                fragment_index: header.fragment_index,
+            });
+        }
+        *slot = Some(payload.to_vec());
+        partial.received += 1;
+        trace!(
+            "message {}: {}/{} fragments",
+            header.message_id,
+            partial.received,
+            header.fragment_count
+        );
+
+        if partial.received < partial.fragments.len() {
+            return Ok(None);
+        }
+        let partial = self.partial.remove(&header.message_id).unwrap();
+        Ok(Some(
+            partial.fragments.into_iter().flatten().flatten().collect(),
+        ))
+    }
+
+    /// drop the messages which were not completed in time and report what they miss
+    pub fn expire(&mut self, now: Instant) -> Vec<ChannelError> {
+        let timeout = self.timeout;
+        let expired: Vec<u16> = self
+            .partial
+            .iter()
+            .filter(|(_, partial)| now.saturating_duration_since(partial.first_seen) >= timeout)
+            .map(|(message_id, _)| *message_id)
+            .collect();
+        expired
+            .into_iter()
+            .map(|message_id| {
+                let partial = self.partial.remove(&message_id).unwrap();
+                warn!("message {} timed out", message_id);
+                ChannelError::IncompleteMessage {
+                    message_id,
+                    missing: partial.missing(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// # Fragmented channel
+/// Send messages larger than one rpmsg buffer by splitting them into numbered fragments.
+/// The remote has to speak the same protocol, see include/rpmsg_fragment.h.
+/// The fragments are sized to the room the channel leaves in an rpmsg buffer,
+/// e.g. smaller with the checksum framing.
+/// Received messages larger than DEFAULT_MAX_MESSAGE_SIZE are refused, see set_max_message_size.
+pub struct FragmentedChannel<C: AbstractRPMsgChannel> {
+    channel: C,
+    fragmenter: Fragmenter,
+    reassembler: Reassembler,
+    // messages which timed out, reported by the following calls to recv
+    expired: VecDeque<ChannelError>,
+    // the largest message recv accepts
+    max_message_size: usize,
+}
+
+impl<C: AbstractRPMsgChannel> FragmentedChannel<C> {
+    /// fail with InvalidFragmentPayloadSize when the channel has no room for a fragment
+    pub fn new(channel: C) -> Result<Self, ChannelError> {
+        FragmentedChannel::with_timeout(channel, DEFAULT_REASSEMBLY_TIMEOUT)
+    }
+
+    /// incomplete messages are dropped timeout after their first fragment
+    pub fn with_timeout(channel: C, timeout: Duration) -> Result<Self, ChannelError> {
+        let payload_size = fragment_payload_size(&channel);
+        let mut reassembler = Reassembler::new(timeout);
+        reassembler.set_max_message_size(DEFAULT_MAX_MESSAGE_SIZE, payload_size)?;
+        Ok(FragmentedChannel {
+            fragmenter: Fragmenter::new(payload_size)?,
+            channel,
+            reassembler,
+            expired: VecDeque::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        })
+    }
+
+    /// accept received messages up to max_message_size
+    pub fn set_max_message_size(&mut self, max_message_size: usize) -> Result<(), ChannelError> {
+        self.reassembler
+            .set_max_message_size(max_message_size, self.fragmenter.fragment_payload_size())?;
+        self.max_message_size = max_message_size;
+        Ok(())
+    }
+
+    // follow the room the channel leaves for a fragment
+    // why: the framing can change after the channel was wrapped, e.g. with set_checksum
+    fn resize_fragments(&mut self) -> Result<(), ChannelError> {
+        let payload_size = fragment_payload_size(&self.channel);
+        if payload_size != self.fragmenter.fragment_payload_size() {
+            self.fragmenter.set_fragment_payload_size(payload_size)?;
+            self.reassembler
+                .set_max_message_size(self.max_message_size, payload_size)?;
+        }
+        Ok(())
+    }
+
+    /// send every fragment of message, stop at the first error of the channel
+    pub fn send(&mut self, message: &[u8]) -> Result<(), ChannelError> {
+        self.resize_fragments()?;
+        for fragment in self.fragmenter.split(message)? {
+            self.channel.send(&fragment)?;
+        }
+        Ok(())
+    }
+
+    /// read fragments until a message is complete
+    /// the errors of the channel are passed through, e.g. when no fragment is pending,
+    /// the fragments received so far are kept for the next call
+    pub fn recv(&mut self) -> Result<Vec<u8>, ChannelError> {
+        self.resize_fragments()?;
+        loop {
+            self.expired.extend(self.reassembler.expire(Instant::now()));
+            if let Some(error) = self.expired.pop_front() {
+                return Err(error);
+            }
+            let fragment = self.channel.read(MAX_RPMSG_BUFF_SIZE as usize)?;
+            if let Some(message) = self.reassembler.push(&fragment, Instant::now())? {
+                return Ok(message);
+            }
+        }
+    }
+
+    pub fn get_ref(&self) -> &C {
+        &self.channel
+    }
+
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.channel
+    }
+
+    pub fn into_inner(self) -> C {
+        self.channel
+    }
+}
+
+fn check_fragment_payload_size(fragment_payload_size: usize) -> Result<(), ChannelError> {
+    if fragment_payload_size == 0 || fragment_payload_size > u16::MAX as usize {
+        return Err(ChannelError::InvalidFragmentPayloadSize {
+            size: fragment_payload_size,
+        });
+    }
+    Ok(())
+}
+
+// the room left for the message in a fragment sent through channel, 0 when there is none
+fn fragment_payload_size<C: AbstractRPMsgChannel>(channel: &C) -> usize {
+    channel
+        .max_message_size()
+        .saturating_sub(FRAGMENT_HEADER_LEN)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::LoopbackRPMsgChannel;
+
+    fn message(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn header_layout() {
+        let header = FragmentHeader {
+            message_id: 0x0102,
+            fragment_index: 3,
+            fragment_count: 4,
+            payload_len: 0,
+        };
+        assert_eq!(header.to_bytes(), [2, 1, 3, 0, 4, 0, 0, 0]);
+        assert_eq!(FragmentHeader::parse(&header.to_bytes()), Ok(header));
+        assert_eq!(std::mem::size_of::<FragmentHeader>(), FRAGMENT_HEADER_LEN);
+    }
+
+    #[test]
+    fn split_and_reassemble() {
+        let mut fragmenter = Fragmenter::default();
+        let mut reassembler = Reassembler::default();
+        let original = message(FRAGMENT_PAYLOAD_MAX_SIZE * 2 + 10);
+        let fragments = fragmenter.split(&original).unwrap();
+        assert_eq!(fragments.len(), 3);
+        assert!(fragments
+            .iter()
+            .all(|f| f.len() <= MAX_RPMSG_BUFF_SIZE as usize));
+
+        let now = Instant::now();
+        // fragments may be handled in any order
+        assert_eq!(reassembler.push(&fragments[2], now), Ok(None));
+        assert_eq!(reassembler.push(&fragments[0], now), Ok(None));
+        assert_eq!(reassembler.push(&fragments[1], now), Ok(Some(original)));
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn empty_message_is_one_fragment() {
+        let mut fragmenter = Fragmenter::default();
+        let fragments = fragmenter.split(&[]).unwrap();
+        assert_eq!(fragments.len(), 1);
+        assert_eq!(
+            Reassembler::default().push(&fragments[0], Instant::now()),
+            Ok(Some(vec![]))
+        );
+    }
+
+    #[test]
+    fn duplicated_fragment_is_reported() {
+        let mut fragmenter = Fragmenter::new(4).unwrap();
+        let mut reassembler = Reassembler::default();
+        let fragments = fragmenter.split(&message(8)).unwrap();
+        let now = Instant::now();
+        assert_eq!(reassembler.push(&fragments[0], now), Ok(None));
+        assert_eq!(
+            reassembler.push(&fragments[0], now),
+            Err(ChannelError::DuplicatedFragment {
+                message_id: 0,
+                fragment_index: 0
+            })
+        );
+        assert_eq!(reassembler.push(&fragments[1], now), Ok(Some(message(8))));
+    }
+
+    #[test]
+    fn incomplete_message_times_out() {
+        let mut fragmenter = Fragmenter::new(4).unwrap();
+        let mut reassembler = Reassembler::new(Duration::from_millis(10));
+        let fragments = fragmenter.split(&message(16)).unwrap();
+        let start = Instant::now();
+        reassembler.push(&fragments[1], start).unwrap();
+        reassembler.push(&fragments[3], start).unwrap();
+        assert!(reassembler.expire(start).is_empty());
+        assert_eq!(
+            reassembler.expire(start + Duration::from_millis(10)),
+            vec![ChannelError::IncompleteMessage {
+                message_id: 0,
+                missing: vec![0, 2]
+            }]
+        );
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn truncated_fragment_is_invalid() {
+        let mut fragmenter = Fragmenter::new(4).unwrap();
+        let fragments = fragmenter.split(&message(8)).unwrap();
+        assert!(matches!(
+            Reassembler::default().push(&fragments[0][..6], Instant::now()),
+            Err(ChannelError::InvalidFragment { .. })
+        ));
+    }
+
+    #[test]
+    fn payload_size_is_checked() {
+        assert_eq!(
+            Fragmenter::new(0).err(),
+            Some(ChannelError::InvalidFragmentPayloadSize { size: 0 })
+        );
+        let mut fragmenter = Fragmenter::new(u16::MAX as usize).unwrap();
+        assert_eq!(
+            fragmenter.set_fragment_payload_size(u16::MAX as usize + 1),
+            Err(ChannelError::InvalidFragmentPayloadSize {
+                size: u16::MAX as usize + 1
+            })
+        );
+        assert_eq!(fragmenter.fragment_payload_size(), u16::MAX as usize);
+    }
+
+    #[test]
+    fn reassembly_is_bounded() {
+        let mut reassembler = Reassembler::default();
+        reassembler.set_max_message_size(16, 4).unwrap();
+        reassembler.set_max_pending(2);
+        let now = Instant::now();
+        // a header announcing the largest message the protocol allows
+        let hostile = FragmentHeader {
+            message_id: 7,
+            fragment_index: 0,
+            fragment_count: u16::MAX,
+            payload_len: 0,
+        };
+        assert!(matches!(
+            reassembler.push(&hostile.to_bytes(), now),
+            Err(ChannelError::InvalidFragment { .. })
+        ));
+        assert_eq!(reassembler.pending(), 0);
+
+        let mut fragmenter = Fragmenter::new(4).unwrap();
+        let first = fragmenter.split(&message(16)).unwrap();
+        let second = fragmenter.split(&message(8)).unwrap();
+        let third = fragmenter.split(&message(8)).unwrap();
+        // 5 fragments of 4 bytes are more than 16 bytes
+        assert!(matches!(
+            reassembler.push(&fragmenter.split(&message(20)).unwrap()[0], now),
+            Err(ChannelError::InvalidFragment { .. })
+        ));
+        assert_eq!(reassembler.push(&first[0], now), Ok(None));
+        assert_eq!(reassembler.push(&second[0], now), Ok(None));
+        assert!(matches!(
+            reassembler.push(&third[0], now),
+            Err(ChannelError::InvalidFragment { .. })
+        ));
+        // the messages already waiting still complete
+        assert_eq!(reassembler.push(&second[1], now), Ok(Some(message(8))));
+        assert_eq!(reassembler.push(&third[0], now), Ok(None));
+        assert_eq!(reassembler.pending(), 2);
+    }
+
+    #[test]
+    fn large_message_over_loopback() {
+        let (host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut host = FragmentedChannel::new(host).unwrap();
+        let mut remote = FragmentedChannel::new(remote).unwrap();
+        let original = message(4096);
+        host.send(&original).unwrap();
+        host.send(b"small").unwrap();
+        assert_eq!(remote.recv().unwrap(), original);
+        assert_eq!(remote.recv().unwrap(), b"small");
+    }
+
+    #[test]
+    fn large_message_over_checksummed_loopback() {
+        let (host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        remote.set_checksum(true);
+        let mut remote = FragmentedChannel::new(remote).unwrap();
+        let mut host = FragmentedChannel::new(host).unwrap();
+        // why: the framing is turned on after wrapping, the fragments shrink with it
+        host.get_mut().set_checksum(true);
+        let original = message(FRAGMENT_PAYLOAD_MAX_SIZE * 3 + 10);
+        host.send(&original).unwrap();
+        assert_eq!(remote.recv().unwrap(), original);
+        assert_eq!(host.get_ref().stats().checksum_mismatches, 0);
+        assert_eq!(remote.get_ref().stats().messages_received, 4);
+    }
+}
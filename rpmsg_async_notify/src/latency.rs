@@ -0,0 +1,388 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// # Latency recorder
+/// Keep the send and receive instants of every message identified by id.
+/// The recorder is a plain value owned by one thread, threads which record on their own
+/// keep their own recorder and merge them at the end, so nothing contends on a global lock.
+#[derive(Debug, Default, Clone)]
+pub struct LatencyRecorder {
+    sent: HashMap<usize, Instant>,
+    received: HashMap<usize, Instant>,
+    // ids in the order their replies came back
+    receive_order: Vec<usize>,
+    // replies received more than once
+    duplicates: usize,
+}
+
+/// latency statistics over the messages which completed a round trip, in microseconds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencyStats {
+    pub min_us: f64,
+    pub max_us: f64,
+    pub mean_us: f64,
+    pub stddev_us: f64,
+    pub p50_us: f64,
+    pub p90_us: f64,
+    pub p99_us: f64,
+    pub p999_us: f64,
+}
+
+/// what a recorder saw, stats is None when no message completed a round trip
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub sent: usize,
+    pub received: usize,
+    pub completed: usize,
+    /// sent but never received
+    pub missing: Vec<usize>,
+    /// received but never sent
+    pub unexpected: Vec<usize>,
+    /// received before they were sent, the clock went wrong
+    pub inverted: Vec<usize>,
+    /// replies which came back after a reply to a later id
+    pub out_of_order: usize,
+    /// replies received more than once
+    pub duplicates: usize,
+    pub stats: Option<LatencyStats>,
+}
+
+/// how a recorder is exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// one `id\tlatency_us` line per message
+    Tsv,
+    /// `id,latency_us` with a header line
+    Csv,
+    /// the summary as a json object
+    Json,
+}
+
+impl ExportFormat {
+    /// pick the format from the extension of path
+    pub fn from_path(path: &Path) -> Option<ExportFormat> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tsv" => Ok(ExportFormat::Tsv),
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(format!("unknown format {}, expected tsv, csv or json", s)),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ExportFormat::Tsv => "tsv",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl LatencyRecorder {
+    pub fn new() -> Self {
+        LatencyRecorder::default()
+    }
+
+    /// reserve room for capacity messages, so recording doesn't allocate
+    pub fn with_capacity(capacity: usize) -> Self {
+        LatencyRecorder {
+            sent: HashMap::with_capacity(capacity),
+            received: HashMap::with_capacity(capacity),
+            receive_order: Vec::with_capacity(capacity),
+            duplicates: 0,
+        }
+    }
+
+    pub fn record_send(&mut self, id: usize, at: Instant) {
+        self.sent.insert(id, at);
+    }
+
+    /// the first reply of an id counts, later ones are only counted as duplicates
+    pub fn record_receive(&mut self, id: usize, at: Instant) {
+        if self.received.contains_key(&id) {
+            self.duplicates += 1;
+            return;
+        }
+        self.received.insert(id, at);
+        self.receive_order.push(id);
+    }
+
+    /// add what other recorded, e.g. the receive side recorded on another thread
+    pub fn merge(&mut self, other: LatencyRecorder) {
+        self.sent.extend(other.sent);
+        self.duplicates += other.duplicates;
+        for id in other.receive_order {
+            let at = other.received[&id];
+            self.record_receive(id, at);
+        }
+    }
+
+    /// latency of every message which completed a round trip, sorted by id
+    pub fn samples(&self) -> Vec<(usize, Duration)> {
+        let mut samples: Vec<(usize, Duration)> = self
+            .received
+            .iter()
+            .filter_map(|(id, receive_time)| {
+                let send_time = self.sent.get(id)?;
+                if receive_time < send_time {
+                    None
+                } else {
+                    Some((*id, receive_time.duration_since(*send_time)))
+                }
+            })
+            .collect();
+        samples.sort_unstable_by_key(|(id, _)| *id);
+        samples
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        let mut missing: Vec<usize> = self
+            .sent
+            .keys()
+            .filter(|id| !self.received.contains_key(id))
+            .copied()
+            .collect();
+        missing.sort_unstable();
+        let mut unexpected: Vec<usize> = self
+            .received
+            .keys()
+            .filter(|id| !self.sent.contains_key(id))
+            .copied()
+            .collect();
+        unexpected.sort_unstable();
+        let mut inverted: Vec<usize> = self
+            .received
+            .iter()
+            .filter(|(id, receive_time)| {
+                self.sent
+                    .get(id)
+                    .is_some_and(|send_time| *receive_time < send_time)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        inverted.sort_unstable();
+
+        let mut latest = None;
+        let mut out_of_order = 0;
+        for id in &self.receive_order {
+            match latest {
+                Some(latest) if id < latest => out_of_order += 1,
+                _ => latest = Some(id),
+            }
+        }
+
+        let mut latencies: Vec<f64> = self
+            .samples()
+            .into_iter()
+            .map(|(_, latency)| latency.as_nanos() as f64 / 1000.0)
+            .collect();
+        LatencySummary {
+            sent: self.sent.len(),
+            received: self.received.len(),
+            completed: latencies.len(),
+            missing,
+            unexpected,
+            inverted,
+            out_of_order,
+            duplicates: self.duplicates,
+            stats: LatencyStats::compute(&mut latencies),
+        }
+    }
+
+    /// one `id\tlatency_us` line per message
+    pub fn write_tsv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for (id, latency) in self.samples() {
+            writeln!(writer, "{}\t{}", id, latency.as_micros())?;
+        }
+        Ok(())
+    }
+
+    /// `id,latency_us` with a header line
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "id,latency_us")?;
+        for (id, latency) in self.samples() {
+            writeln!(writer, "{},{}", id, latency.as_micros())?;
+        }
+        Ok(())
+    }
+
+    /// the summary as a json object
+    pub fn write_summary_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.summary()).map_err(io::Error::from)
+    }
+
+    pub fn export<W: Write>(&self, format: ExportFormat, writer: W) -> io::Result<()> {
+        match format {
+            ExportFormat::Tsv => self.write_tsv(writer),
+            ExportFormat::Csv => self.write_csv(writer),
+            ExportFormat::Json => self.write_summary_json(writer),
+        }
+    }
+}
+
+impl LatencyStats {
+    // latencies are sorted in place
+    fn compute(latencies: &mut [f64]) -> Option<LatencyStats> {
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        let count = latencies.len() as f64;
+        let mean = latencies.iter().sum::<f64>() / count;
+        let variance = latencies.iter().map(|l| (l - mean).powi(2)).sum::<f64>() / count;
+        Some(LatencyStats {
+            min_us: latencies[0],
+            max_us: latencies[latencies.len() - 1],
+            mean_us: mean,
+            stddev_us: variance.sqrt(),
+            p50_us: percentile(latencies, 50.0),
+            p90_us: percentile(latencies, 90.0),
+            p99_us: percentile(latencies, 99.0),
+            p999_us: percentile(latencies, 99.9),
+        })
+    }
+}
+
+/// nearest-rank percentile of sorted
+fn percentile(sorted: &[f64], percent: f64) -> f64 {
+    // in permille, so 99.9 doesn't pick up a rounding error
+    let permille = (percent * 10.0).round() as usize;
+    let rank = (permille * sorted.len()).div_ceil(1000);
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl fmt::Display for LatencySummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "sent: {}, received: {}, completed: {}",
+            self.sent, self.received, self.completed
+        )?;
+        writeln!(
+            f,
+            "missing: {}, unexpected: {}, inverted: {}, out of order: {}, duplicates: {}",
+            self.missing.len(),
+            self.unexpected.len(),
+            self.inverted.len(),
+            self.out_of_order,
+            self.duplicates
+        )?;
+        match &self.stats {
+            Some(stats) => write!(
+                f,
+                "min: {:.1}us, max: {:.1}us, mean: {:.1}us, stddev: {:.1}us, p50: {:.1}us, p90: {:.1}us, p99: {:.1}us, p99.9: {:.1}us",
+                stats.min_us,
+                stats.max_us,
+                stats.mean_us,
+                stats.stddev_us,
+                stats.p50_us,
+                stats.p90_us,
+                stats.p99_us,
+                stats.p999_us
+            ),
+            None => write!(f, "no message completed a round trip"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorder_with(latencies_us: &[(usize, u64)]) -> LatencyRecorder {
+        let start = Instant::now();
+        let mut recorder = LatencyRecorder::new();
+        for (id, latency) in latencies_us {
+            recorder.record_send(*id, start);
+            recorder.record_receive(*id, start + Duration::from_micros(*latency));
+        }
+        recorder
+    }
+
+    #[test]
+    fn statistics() {
+        let latencies: Vec<(usize, u64)> = (1..=1000).map(|i| (i, i as u64)).collect();
+        let stats = recorder_with(&latencies).summary().stats.unwrap();
+        assert_eq!(stats.min_us, 1.0);
+        assert_eq!(stats.max_us, 1000.0);
+        assert_eq!(stats.mean_us, 500.5);
+        assert_eq!(stats.p50_us, 500.0);
+        assert_eq!(stats.p90_us, 900.0);
+        assert_eq!(stats.p99_us, 990.0);
+        assert_eq!(stats.p999_us, 999.0);
+        assert!((stats.stddev_us - 288.675).abs() < 0.001);
+    }
+
+    #[test]
+    fn flags_missing_unexpected_and_out_of_order() {
+        let start = Instant::now();
+        let mut recorder = LatencyRecorder::new();
+        for id in 0..4 {
+            recorder.record_send(id, start);
+        }
+        recorder.record_receive(1, start + Duration::from_micros(10));
+        recorder.record_receive(0, start + Duration::from_micros(20));
+        recorder.record_receive(0, start + Duration::from_micros(30));
+        recorder.record_receive(3, start + Duration::from_micros(40));
+        recorder.record_receive(7, start + Duration::from_micros(50));
+
+        let summary = recorder.summary();
+        assert_eq!(summary.sent, 4);
+        assert_eq!(summary.received, 4);
+        assert_eq!(summary.completed, 3);
+        assert_eq!(summary.missing, vec![2]);
+        assert_eq!(summary.unexpected, vec![7]);
+        assert_eq!(summary.out_of_order, 1);
+        assert_eq!(summary.duplicates, 1);
+    }
+
+    #[test]
+    fn merge_recorders_of_two_threads() {
+        let start = Instant::now();
+        let mut send_side = LatencyRecorder::new();
+        let mut receive_side = LatencyRecorder::new();
+        send_side.record_send(1, start);
+        receive_side.record_receive(1, start + Duration::from_micros(5));
+        send_side.merge(receive_side);
+        assert_eq!(send_side.samples(), vec![(1, Duration::from_micros(5))]);
+    }
+
+    #[test]
+    fn export_formats() {
+        let recorder = recorder_with(&[(2, 20), (1, 10)]);
+        let mut tsv = Vec::new();
+        recorder.write_tsv(&mut tsv).unwrap();
+        assert_eq!(String::from_utf8(tsv).unwrap(), "1\t10\n2\t20\n");
+        let mut csv = Vec::new();
+        recorder.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "id,latency_us\n1,10\n2,20\n"
+        );
+        let mut json = Vec::new();
+        recorder.write_summary_json(&mut json).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(json["completed"], 2);
+        assert_eq!(json["stats"]["max_us"], 20.0);
+        assert_eq!(
+            ExportFormat::from_path(Path::new("run.json")),
+            Some(ExportFormat::Json)
+        );
+    }
+}
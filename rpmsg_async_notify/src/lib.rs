@@ -4,38 +4,56 @@ extern crate snafu;
 #[macro_use]
 extern crate lazy_static;
 use crate::channel::{
-    ioctl_create_endpt, register_rpmsg_driver_for_device, search_control_interface,
-    search_endpoint_path_by_name, RPMsgEndpointInfo,
+    create_endpoint_node, open_control_interface, wait_for_rpmsg_device, ChannelConfig,
+    ChannelError,
 };
 use bincode::deserialize;
 use bincode::serialize_into;
 use bincode::serialized_size;
 use cpu_time::ProcessTime;
-use log::trace;
 use nix::libc::clock_t;
 use nix::sys::signal::{self, SigHandler};
 use nix::unistd::write;
 use nix::{
-    fcntl::{open, OFlag},
     libc::{__u32, access, fcntl, getpid, signal, F_GETFL, F_SETFL, F_SETOWN, O_ASYNC, SIGIO},
-    sys::stat::Mode,
     unistd::{read, AccessFlags},
 };
 use remote_proc::RemoteprocManager;
 use serde::{Deserialize, Serialize};
 use signal_hook::consts::SIGIO as SIGIO_HOOK;
 use signal_hook::iterator::Signals;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::mem;
+use std::os::unix::prelude::OwnedFd;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
 use std::sync::Mutex;
 use std::thread::{self, sleep};
 use std::time::{Duration, Instant};
-use std::{mem, path::Path};
+use sysfs::SysfsRoot;
 
+#[cfg(feature = "tokio")]
+pub mod async_endpoint;
+pub mod capture;
 pub mod channel;
+pub mod checksum;
+pub mod device;
+pub mod echo;
+pub mod firmware;
+pub mod fragment;
+pub mod latency;
+pub mod loopback;
+pub mod notify;
+pub mod pool;
 pub mod remote_proc;
+#[cfg(any(test, feature = "test-support"))]
+pub mod remote_proc_sim;
+pub mod resource_table;
+pub mod rpc;
+pub mod stats;
+pub mod sysfs;
 pub mod time_utils;
+pub mod typed;
+pub mod uevent;
 
 pub const RPMSG_HEADER_LEN: u32 = 16;
 pub const MAX_RPMSG_BUFF_SIZE: u32 = (512 - RPMSG_HEADER_LEN);
@@ -45,11 +63,6 @@ pub const NUM_PAYLOADS: usize = 1_00000;
 lazy_static! {
     pub static ref receive_payload: Mutex<[u8; 1024]> = Mutex::new([0u8; 1024]);
     pub static ref send_payload: Mutex<[u8; 1024]> = Mutex::new([0u8; 1024]);
-    pub static ref send_tick: Mutex<HashMap<usize, clock_t>> = Mutex::new(HashMap::new());
-    pub static ref receive_tick: Mutex<HashMap<usize, clock_t>> = Mutex::new(HashMap::new());
-    pub static ref send_tick_instant: Mutex<HashMap<usize, Instant>> = Mutex::new(HashMap::new());
-    pub static ref receive_tick_instant: Mutex<HashMap<usize, Instant>> =
-        Mutex::new(HashMap::new());
 }
 pub mod ffi {
     extern "C" {
@@ -78,44 +91,97 @@ pub struct TimeStampTick {
     pub id: u64,
     pub time_stamp: clock_t,
 }
-pub fn prepare_environment() -> PathBuf {
-    // start build the rpmsg communication channel
-    let virtio_id = "virtio0";
-    let channel_name = "rpmsg-openamp-demo-channel";
-    let version_number = "1.0";
-    let device_name = format!("{}.{}.-{}", virtio_id, channel_name, version_number);
-    let rpmsg_device_path = Path::new("/sys/bus/rpmsg/devices").join(device_name.clone());
-    while !rpmsg_device_path.exists() {}
+/// an endpoint created by prepare_environment
+/// the control interface it was created through is closed on drop,
+/// keep it as long as the endpoint is used
+pub struct PreparedEndpoint {
+    endpoint_path: PathBuf,
+    _ctrl_interface_handler: OwnedFd,
+}
 
-    // register RPMsg driver
-    let rpmsg_driver_name = String::from("rpmsg_char_notify");
-    register_rpmsg_driver_for_device(device_name.clone(), rpmsg_driver_name).unwrap();
-    trace!("Register rpmsg driver");
+impl PreparedEndpoint {
+    /// the path to the device node of the endpoint
+    pub fn endpoint_path(&self) -> &Path {
+        &self.endpoint_path
+    }
+}
 
-    // look for control interface of character driver
-    let ctrl_interface_name =
-        search_control_interface(device_name.clone(), "rpmsg_ctrl".to_string()).unwrap();
-    let ctrl_interface_path = Path::new("/dev").join(ctrl_interface_name.clone());
-    let ctrl_interface_handler = open(&ctrl_interface_path, OFlag::O_RDWR, Mode::empty()).unwrap();
+/// bind the driver of config to its rpmsg device and create the endpoint
+pub fn prepare_environment(config: &ChannelConfig) -> Result<PreparedEndpoint, ChannelError> {
+    prepare_environment_in(&SysfsRoot::default(), config)
+}
 
-    // create endpoint
-    let endpoint = RPMsgEndpointInfo::new(&channel_name, RPMSG_ADDR_ANY, 0).unwrap();
-    trace!("creating endpoint: {}", channel_name);
-    let ret = unsafe { ioctl_create_endpt(ctrl_interface_handler, &[endpoint]).unwrap() };
-    if ret == -1 {
-        panic!("can't create endpoint");
-    }
-    let endpoint_path_str =
-        search_endpoint_path_by_name(ctrl_interface_name.clone(), channel_name.to_string())
-            .unwrap();
-    Path::new(&endpoint_path_str).to_path_buf()
+/// same as prepare_environment, but look for the rpmsg devices under sysfs
+pub fn prepare_environment_in(
+    sysfs: &SysfsRoot,
+    config: &ChannelConfig,
+) -> Result<PreparedEndpoint, ChannelError> {
+    let device_name = config.device_name()?.to_string();
+    wait_for_rpmsg_device(sysfs, &device_name, config.device_timeout)?;
+    let (ctrl_interface_name, ctrl_interface_handler) =
+        open_control_interface(sysfs, &device_name, &config.driver_name)?;
+    let endpoint_path_str = create_endpoint_node(
+        sysfs,
+        &ctrl_interface_name,
+        &ctrl_interface_handler,
+        &config.endpoint(),
+    )?;
+    // why: the caller opens the endpoint by path, the control interface stays open
+    // with the returned endpoint so the endpoint is not torn down under the caller
+    Ok(PreparedEndpoint {
+        endpoint_path: PathBuf::from(endpoint_path_str),
+        _ctrl_interface_handler: ctrl_interface_handler,
+    })
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use crate::sysfs::tests::fake_rpmsg_tree;
+    use nix::errno::Errno;
+    use std::fs;
+
     #[test]
     fn it_works() {
         let result = 2 + 2;
         assert_eq!(result, 4);
     }
+
+    #[test]
+    fn prepare_environment_reports_failures() {
+        let root = tempfile::tempdir().unwrap();
+        let config = ChannelConfig {
+            device_timeout: Duration::from_millis(20),
+            ..ChannelConfig::default()
+        };
+        assert_eq!(
+            prepare_environment_in(&SysfsRoot::new(root.path()), &config).err(),
+            Some(ChannelError::DeviceTimeout {
+                device_name: "virtio0.rpmsg-openamp-demo-channel.-1.0".to_string(),
+                timeout: config.device_timeout,
+            })
+        );
+
+        // the fake control interface is a plain file, the kernel refuses to create the endpoint
+        let sysfs = fake_rpmsg_tree(
+            root.path(),
+            &config.device_name().unwrap().to_string(),
+            &config.driver_name,
+        );
+        assert_eq!(
+            prepare_environment_in(&sysfs, &config).err(),
+            Some(ChannelError::SysError {
+                source: Errno::ENOTTY
+            })
+        );
+        assert_eq!(
+            fs::read_to_string(
+                sysfs
+                    .rpmsg_device_dir(&config.device_name().unwrap().to_string())
+                    .join("driver_override")
+            )
+            .unwrap(),
+            "rpmsg_char_notify"
+        );
+    }
 }
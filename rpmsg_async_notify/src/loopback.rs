@@ -0,0 +1,320 @@
+use crate::channel::{AbstractRPMsgChannel, ChannelError, RPMsgEndpoint};
+use crate::device::RPMsgDeviceName;
+use crate::stats::EndpointStats;
+use log::trace;
+use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
+use std::collections::HashMap;
+use std::os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+lazy_static! {
+    // the remote halves of the channels created by instantiate, keyed by rpmsg device name
+    // an entry lives as long as its host half, None once take_peer picked the remote half up
+    static ref LOOPBACK_PEERS: Mutex<HashMap<String, Option<LoopbackRPMsgChannel>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// # Loopback
+/// An in-process channel which behaves like an rpmsg endpoint without the hardware.
+/// Both halves are SEQPACKET sockets, so message boundaries are preserved,
+/// reads are non-blocking and a message can't be larger than MAX_RPMSG_BUFF_SIZE.
+/// One half is used by the code under test, the other half plays the RPU.
+pub struct LoopbackRPMsgChannel {
+    endpoint: RPMsgEndpoint,
+    // the device name a host half created by instantiate is registered under
+    registered_as: Option<String>,
+}
+
+impl LoopbackRPMsgChannel {
+    /// create a connected pair of channels, the first is meant for the host,
+    /// the second for the test code acting as the remote processor
+    pub fn pair() -> Result<(LoopbackRPMsgChannel, LoopbackRPMsgChannel), ChannelError> {
+        LoopbackRPMsgChannel::named_pair("loopback")
+    }
+
+    /// same as pair, the name shows up in the errors of the endpoints
+    pub fn named_pair(
+        name: &str,
+    ) -> Result<(LoopbackRPMsgChannel, LoopbackRPMsgChannel), ChannelError> {
+        let (host, remote) = socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+        )?;
+        // why: socketpair returned two new descriptors which nobody else owns
+        let (host, remote) = unsafe { (OwnedFd::from_raw_fd(host), OwnedFd::from_raw_fd(remote)) };
+        Ok((
+            LoopbackRPMsgChannel {
+                endpoint: RPMsgEndpoint::from_owned_fd(format!("{}:host", name), host),
+                registered_as: None,
+            },
+            LoopbackRPMsgChannel {
+                endpoint: RPMsgEndpoint::from_owned_fd(format!("{}:remote", name), remote),
+                registered_as: None,
+            },
+        ))
+    }
+
+    /// take the remote half of the channel created by instantiate for device_name
+    /// the device name is formatted as "{virtio_id}.{channel_name}.-{version_number}"
+    /// a remote half nobody takes is dropped with its host half
+    pub fn take_peer(device_name: &str) -> Option<LoopbackRPMsgChannel> {
+        LOOPBACK_PEERS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get_mut(device_name)
+            .and_then(Option::take)
+    }
+
+    /// the traffic and errors of this half, see RPMsgEndpoint::stats
+    pub fn stats(&self) -> EndpointStats {
+        self.endpoint.stats()
+    }
+
+    pub fn reset_stats(&self) {
+        self.endpoint.reset_stats()
+    }
+
+    /// turn the checksum framing on or off, see RPMsgEndpoint::set_checksum
+    pub fn set_checksum(&mut self, enabled: bool) {
+        self.endpoint.set_checksum(enabled)
+    }
+
+    /// wait up to timeout for a message and read it, see RPMsgEndpoint::read_timeout
+    pub fn read_timeout(
+        &mut self,
+        capacity: usize,
+        timeout: Duration,
+    ) -> Result<Vec<u8>, ChannelError> {
+        self.endpoint.read_timeout(capacity, timeout)
+    }
+
+    /// wait up to timeout until a message can be read
+    pub fn wait_readable(&self, timeout: Duration) -> Result<(), ChannelError> {
+        self.endpoint.wait_readable(timeout)
+    }
+
+    /// wait up to timeout until a message can be sent
+    pub fn wait_writable(&self, timeout: Duration) -> Result<(), ChannelError> {
+        self.endpoint.wait_writable(timeout)
+    }
+}
+
+impl AsRawFd for LoopbackRPMsgChannel {
+    fn as_raw_fd(&self) -> RawFd {
+        self.endpoint.as_raw_fd()
+    }
+}
+
+impl Drop for LoopbackRPMsgChannel {
+    fn drop(&mut self) {
+        if let Some(device_name) = self.registered_as.take() {
+            // why: the remote half is dropped under the lock, it isn't registered so it doesn't lock
+            LOOPBACK_PEERS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .remove(&device_name);
+        }
+    }
+}
+
+impl AbstractRPMsgChannel for LoopbackRPMsgChannel {
+    /// create a pair and keep the remote half until take_peer picks it up
+    /// or the host half is dropped, a device name can't be instantiated twice at a time
+    fn instantiate(
+        channel_name: String,
+        virtio_id: String,
+        version_number: String,
+    ) -> Result<Self, ChannelError> {
+        let device_name =
+            RPMsgDeviceName::with_version(&virtio_id, &channel_name, &version_number)?.to_string();
+        trace!("Open loopback rpmsg dev {}", device_name);
+        // why: a test which panicked while holding the lock leaves the map consistent,
+        // every change to it is a single insert, take or remove
+        let mut peers = LOOPBACK_PEERS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if peers.contains_key(&device_name) {
+            return Err(ChannelError::DeviceInUse { device_name });
+        }
+        let (mut host, remote) = LoopbackRPMsgChannel::named_pair(&device_name)?;
+        host.registered_as = Some(device_name.clone());
+        peers.insert(device_name, Some(remote));
+        Ok(host)
+    }
+
+    fn send(&mut self, message: &[u8]) -> Result<(), ChannelError> {
+        let max_size = self.max_message_size();
+        if message.len() > max_size {
+            return Err(ChannelError::MessageTooLarge {
+                message_size: message.len(),
+                max_size,
+            });
+        }
+        self.endpoint.send(message)
+    }
+
+    fn read(&mut self, capacity: usize) -> Result<Vec<u8>, ChannelError> {
+        self.endpoint.read(capacity)
+    }
+
+    fn max_message_size(&self) -> usize {
+        self.endpoint.max_message_size()
+    }
+
+    fn read_into(&mut self, buf: &mut [u8]) -> Result<usize, ChannelError> {
+        self.endpoint.read_into(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MAX_RPMSG_BUFF_SIZE;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn messages_keep_their_boundaries() {
+        let (mut host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        host.send(b"hello").unwrap();
+        host.send(b"rpu").unwrap();
+        assert_eq!(remote.read(MAX_RPMSG_BUFF_SIZE as usize).unwrap(), b"hello");
+        assert_eq!(remote.read(MAX_RPMSG_BUFF_SIZE as usize).unwrap(), b"rpu");
+        remote.send(b"reply").unwrap();
+        assert_eq!(host.read(MAX_RPMSG_BUFF_SIZE as usize).unwrap(), b"reply");
+    }
+
+    #[test]
+    fn empty_channel_would_block() {
+        let (mut host, _remote) = LoopbackRPMsgChannel::pair().unwrap();
+        assert_eq!(
+            host.read(MAX_RPMSG_BUFF_SIZE as usize),
+            Err(ChannelError::WouldBlock {
+                endpoint_name: "loopback:host".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn read_with_timeout() {
+        let (mut host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let timeout = Duration::from_millis(5);
+        let start = Instant::now();
+        assert_eq!(
+            host.read_timeout(MAX_RPMSG_BUFF_SIZE as usize, timeout),
+            Err(ChannelError::Timeout {
+                endpoint_name: "loopback:host".to_string(),
+                timeout,
+            })
+        );
+        assert!(start.elapsed() >= timeout);
+        assert!(host.wait_readable(Duration::ZERO).is_err());
+        host.wait_writable(Duration::ZERO).unwrap();
+
+        let reply = thread::spawn(move || {
+            let request = remote
+                .read_timeout(MAX_RPMSG_BUFF_SIZE as usize, Duration::from_secs(5))
+                .unwrap();
+            remote.send(&request).unwrap();
+            remote
+        });
+        host.send(b"ping").unwrap();
+        assert_eq!(
+            host.read_timeout(MAX_RPMSG_BUFF_SIZE as usize, Duration::from_secs(5))
+                .unwrap(),
+            b"ping"
+        );
+        drop(reply.join().unwrap());
+        // why: the peer hung up, so the endpoint is readable and the read returns EOF
+        host.wait_readable(Duration::from_secs(5)).unwrap();
+        assert!(host.read(MAX_RPMSG_BUFF_SIZE as usize).unwrap().is_empty());
+    }
+
+    #[test]
+    fn oversize_message_is_rejected() {
+        let (mut host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let largest = vec![7u8; MAX_RPMSG_BUFF_SIZE as usize];
+        host.send(&largest).unwrap();
+        assert_eq!(remote.read(MAX_RPMSG_BUFF_SIZE as usize).unwrap(), largest);
+        assert_eq!(
+            host.send(&[0u8; MAX_RPMSG_BUFF_SIZE as usize + 1]),
+            Err(ChannelError::MessageTooLarge {
+                message_size: MAX_RPMSG_BUFF_SIZE as usize + 1,
+                max_size: MAX_RPMSG_BUFF_SIZE as usize,
+            })
+        );
+    }
+
+    #[test]
+    fn read_into_reports_overflow() {
+        let (mut host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        host.send(b"image 0x80000000").unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(
+            remote.read_into(&mut buf),
+            Err(ChannelError::MessageBufferOverflow {
+                capacity: 8,
+                message_size: 16,
+            })
+        );
+        host.send(b"image 0x").unwrap();
+        assert_eq!(remote.read_into(&mut buf), Ok(8));
+        assert_eq!(&buf, b"image 0x");
+        assert_eq!(remote.stats().overflows, 1);
+    }
+
+    #[test]
+    fn instantiate_registers_peer() {
+        let mut host = LoopbackRPMsgChannel::instantiate(
+            "loopback-test-channel".to_string(),
+            "virtio7".to_string(),
+            "1.0".to_string(),
+        )
+        .unwrap();
+        let mut remote =
+            LoopbackRPMsgChannel::take_peer("virtio7.loopback-test-channel.-1.0").unwrap();
+        assert!(LoopbackRPMsgChannel::take_peer("virtio7.loopback-test-channel.-1.0").is_none());
+        remote.send(b"announce").unwrap();
+        assert_eq!(host.read(32).unwrap(), b"announce");
+
+        let instantiate = || {
+            LoopbackRPMsgChannel::instantiate(
+                "loopback-test-channel".to_string(),
+                "virtio7".to_string(),
+                "1.0".to_string(),
+            )
+        };
+        assert!(matches!(
+            instantiate(),
+            Err(ChannelError::DeviceInUse { device_name })
+                if device_name == "virtio7.loopback-test-channel.-1.0"
+        ));
+        drop(host);
+        // why: the host half is gone, so the name is free and the new remote half isn't taken
+        let host = instantiate().unwrap();
+        drop(host);
+        assert!(LoopbackRPMsgChannel::take_peer("virtio7.loopback-test-channel.-1.0").is_none());
+        assert!(instantiate().is_ok());
+    }
+
+    #[test]
+    fn poisoned_registry_still_works() {
+        let _ = thread::spawn(|| {
+            let _peers = LOOPBACK_PEERS.lock().unwrap();
+            panic!("poison the loopback registry");
+        })
+        .join();
+        let host = LoopbackRPMsgChannel::instantiate(
+            "loopback-poison-channel".to_string(),
+            "virtio8".to_string(),
+            "1.0".to_string(),
+        )
+        .unwrap();
+        assert!(LoopbackRPMsgChannel::take_peer("virtio8.loopback-poison-channel.-1.0").is_some());
+        drop(host);
+        assert!(LoopbackRPMsgChannel::take_peer("virtio8.loopback-poison-channel.-1.0").is_none());
+    }
+}
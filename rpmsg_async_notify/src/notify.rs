@@ -0,0 +1,718 @@
+use crate::channel::{AbstractRPMsgChannel, ChannelError};
+use crate::pool::{BufferPool, LeasedBuffer};
+use crate::MAX_RPMSG_BUFF_SIZE;
+use log::{error, trace, warn};
+use nix::errno::Errno;
+use nix::libc::{
+    self, epoll_event, getpid, FD_CLOEXEC, F_GETFD, F_GETFL, F_SETFD, F_SETFL, F_SETOWN, O_ASYNC,
+};
+use nix::poll::{poll, PollFd, PollFlags};
+use serde::Serialize;
+use signal_hook::consts::SIGIO;
+use signal_hook::iterator::Signals;
+use std::fmt;
+use std::io;
+use std::os::unix::prelude::{AsRawFd, RawFd};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+macro_rules! syscall {
+    ($fn: ident ( $($arg: expr),* $(,)* ) ) => {{
+        let res = unsafe { nix::libc::$fn($($arg, )*) };
+        if res == -1 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(res)
+        }
+    }};
+}
+
+/// how often the blocking strategy checks whether it was asked to stop
+const BLOCKING_POLL_INTERVAL_MS: i32 = 100;
+/// how often a receive thread waiting for a buffer of its pool checks whether it was asked to stop
+const POOL_WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+// tags of the descriptors registered to epoll
+const EPOLL_CHANNEL_TOKEN: u64 = 0;
+const EPOLL_WAKER_TOKEN: u64 = 1;
+
+/// the channel shared between the receive thread of a strategy and the senders
+pub type SharedChannel<C> = Arc<Mutex<C>>;
+
+/// where a strategy delivers the received messages
+pub enum MessageSink {
+    /// call the closure on the receive thread for every message
+    Callback(Box<dyn FnMut(Vec<u8>) + Send>),
+    /// send every message to the channel, the strategy stops when the receiver is gone
+    Channel(Sender<Vec<u8>>),
+    /// read every message into a buffer leased from the pool and hand it to the closure,
+    /// the receive thread doesn't allocate. When the pool runs dry the messages stay
+    /// in the channel until a buffer goes back to the pool.
+    Leased(BufferPool, Box<dyn FnMut(LeasedBuffer) + Send>),
+}
+
+// what became of the next message of the channel
+enum Received {
+    Delivered,
+    /// the read returned the end of file
+    HungUp,
+    /// nobody listens any more
+    SinkGone,
+    /// the message was left in the channel, no buffer of the pool is free
+    PoolExhausted(BufferPool),
+}
+
+impl MessageSink {
+    // read the next message of channel and hand it over
+    fn receive<C: AbstractRPMsgChannel>(
+        &mut self,
+        channel: &Mutex<C>,
+    ) -> Result<Received, ChannelError> {
+        // why: lock per message so senders are not blocked while the sink works
+        match self {
+            MessageSink::Leased(pool, handler) => {
+                let message = match pool.receive(&mut *channel.lock().unwrap()) {
+                    Ok(message) => message,
+                    Err(ChannelError::BufferPoolExhausted { .. }) => {
+                        return Ok(Received::PoolExhausted(pool.clone()))
+                    }
+                    Err(e) => return Err(e),
+                };
+                if message.is_empty() {
+                    return Ok(Received::HungUp);
+                }
+                trace!("received {} bytes", message.len());
+                handler(message);
+                Ok(Received::Delivered)
+            }
+            MessageSink::Callback(callback) => {
+                let message = channel.lock().unwrap().read(MAX_RPMSG_BUFF_SIZE as usize)?;
+                if message.is_empty() {
+                    return Ok(Received::HungUp);
+                }
+                trace!("received {} bytes", message.len());
+                callback(message);
+                Ok(Received::Delivered)
+            }
+            MessageSink::Channel(sender) => {
+                let message = channel.lock().unwrap().read(MAX_RPMSG_BUFF_SIZE as usize)?;
+                if message.is_empty() {
+                    return Ok(Received::HungUp);
+                }
+                trace!("received {} bytes", message.len());
+                match sender.send(message) {
+                    Ok(()) => Ok(Received::Delivered),
+                    Err(_) => Ok(Received::SinkGone),
+                }
+            }
+        }
+    }
+}
+
+/// # Notification strategy
+/// A way to learn that messages arrived on a non-blocking channel.
+/// start spawns a receive thread which reads every message and hands it to the sink,
+/// the thread runs until the handle is stopped or dropped, or the remote hangs up.
+/// A message too large or corrupted is dropped with a warning, the next ones are delivered.
+pub trait NotificationStrategy<C>
+where
+    C: AbstractRPMsgChannel + AsRawFd + Send + 'static,
+{
+    fn start(
+        self: Box<Self>,
+        channel: SharedChannel<C>,
+        sink: MessageSink,
+    ) -> Result<NotifierHandle, ChannelError>;
+}
+
+/// the strategies known by the library, so applications can pick one by configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationKind {
+    Epoll,
+    Sigio,
+    Blocking,
+}
+
+impl NotificationKind {
+    pub fn strategy<C>(&self) -> Box<dyn NotificationStrategy<C>>
+    where
+        C: AbstractRPMsgChannel + AsRawFd + Send + 'static,
+    {
+        match self {
+            NotificationKind::Epoll => Box::new(EpollStrategy),
+            NotificationKind::Sigio => Box::new(SigioStrategy),
+            NotificationKind::Blocking => Box::new(BlockingStrategy),
+        }
+    }
+}
+
+impl FromStr for NotificationKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "epoll" => Ok(NotificationKind::Epoll),
+            "sigio" => Ok(NotificationKind::Sigio),
+            "blocking" => Ok(NotificationKind::Blocking),
+            _ => Err(format!(
+                "unknown notification strategy {}, expected epoll, sigio or blocking",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NotificationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            NotificationKind::Epoll => "epoll",
+            NotificationKind::Sigio => "sigio",
+            NotificationKind::Blocking => "blocking",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// controls the receive thread of a strategy, the thread is stopped on drop
+pub struct NotifierHandle {
+    stop: Arc<AtomicBool>,
+    // wake the receive thread up so it notices the stop flag
+    waker: Box<dyn Fn() + Send>,
+    thread: Option<JoinHandle<Result<(), ChannelError>>>,
+}
+
+impl NotifierHandle {
+    /// stop the receive thread and report the error it stopped with, if any
+    pub fn stop(mut self) -> Result<(), ChannelError> {
+        self.shutdown()
+    }
+
+    /// true once the receive thread returned, e.g. because the remote hung up
+    pub fn is_finished(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_none_or(|thread| thread.is_finished())
+    }
+
+    fn shutdown(&mut self) -> Result<(), ChannelError> {
+        self.stop.store(true, Ordering::SeqCst);
+        (self.waker)();
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or_else(|_| {
+                Err(ChannelError::IOError {
+                    error: "receive thread panicked".to_string(),
+                })
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for NotifierHandle {
+    fn drop(&mut self) {
+        if let Err(e) = self.shutdown() {
+            warn!("receive thread stopped with error: {}", e);
+        }
+    }
+}
+
+fn io_error(e: io::Error) -> ChannelError {
+    ChannelError::IOError {
+        error: format!("{:?}", e),
+    }
+}
+
+/// read until no message is pending, return false once the sink is gone or the remote hung up
+/// a leased sink whose pool ran dry waits for a buffer, or until stop is set
+fn drain<C: AbstractRPMsgChannel>(
+    channel: &Mutex<C>,
+    sink: &mut MessageSink,
+    stop: &AtomicBool,
+) -> Result<bool, ChannelError> {
+    loop {
+        match sink.receive(channel) {
+            Ok(Received::Delivered) => {}
+            // why: an empty read is the end of file, the remote hung up and nothing else comes
+            Ok(Received::HungUp) => {
+                trace!("stop receiving, the remote hung up");
+                return Ok(false);
+            }
+            Ok(Received::SinkGone) => {
+                trace!("stop receiving, nobody listens");
+                return Ok(false);
+            }
+            Ok(Received::PoolExhausted(pool)) => {
+                warn!(
+                    "all {} buffers of the pool are in use, waiting for one to come back",
+                    pool.capacity()
+                );
+                while !pool.wait_available(POOL_WAIT_INTERVAL) {
+                    if stop.load(Ordering::SeqCst) {
+                        return Ok(false);
+                    }
+                }
+            }
+            Err(ChannelError::WouldBlock { .. }) => return Ok(true),
+            // why: one bad message of the remote must not stop the delivery of the next ones,
+            // the endpoint counts it in its stats
+            Err(e) if e.is_bad_message() => warn!("dropped a received message, {:?}", e),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// create the epoll handler
+pub fn epoll_create() -> io::Result<RawFd> {
+    let fd = syscall!(epoll_create1(0))?;
+    if let Ok(flags) = syscall!(fcntl(fd, F_GETFD)) {
+        let _ = syscall!(fcntl(fd, F_SETFD, flags | FD_CLOEXEC))?;
+    }
+    Ok(fd)
+}
+
+/// bind epoll handler's interest
+pub fn add_interest(epoll_fd: RawFd, fd: RawFd, mut event: epoll_event) -> io::Result<()> {
+    syscall!(epoll_ctl(epoll_fd, libc::EPOLL_CTL_ADD, fd, &mut event))?;
+    Ok(())
+}
+
+/// modify epoll handler's interest
+pub fn modify_interest(epoll_fd: RawFd, fd: RawFd, mut event: epoll_event) -> io::Result<()> {
+    syscall!(epoll_ctl(epoll_fd, libc::EPOLL_CTL_MOD, fd, &mut event))?;
+    Ok(())
+}
+
+/// remove the fd from epoll handler's interest
+pub fn remove_interest(epoll_fd: RawFd, fd: RawFd) -> io::Result<()> {
+    syscall!(epoll_ctl(
+        epoll_fd,
+        libc::EPOLL_CTL_DEL,
+        fd,
+        std::ptr::null_mut()
+    ))?;
+    Ok(())
+}
+
+/// wait on an epoll instance watching the channel and an eventfd used to stop the thread
+pub struct EpollStrategy;
+
+impl<C> NotificationStrategy<C> for EpollStrategy
+where
+    C: AbstractRPMsgChannel + AsRawFd + Send + 'static,
+{
+    fn start(
+        self: Box<Self>,
+        channel: SharedChannel<C>,
+        mut sink: MessageSink,
+    ) -> Result<NotifierHandle, ChannelError> {
+        let channel_fd = channel.lock().unwrap().as_raw_fd();
+        let epoll_fd = epoll_create().map_err(io_error)?;
+        let waker_fd = match syscall!(eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK)) {
+            Ok(fd) => fd,
+            Err(e) => {
+                let _ = syscall!(close(epoll_fd));
+                return Err(io_error(e));
+            }
+        };
+        let interests = add_interest(
+            epoll_fd,
+            channel_fd,
+            epoll_event {
+                events: libc::EPOLLIN as u32,
+                u64: EPOLL_CHANNEL_TOKEN,
+            },
+        )
+        .and_then(|_| {
+            add_interest(
+                epoll_fd,
+                waker_fd,
+                epoll_event {
+                    events: libc::EPOLLIN as u32,
+                    u64: EPOLL_WAKER_TOKEN,
+                },
+            )
+        });
+        if let Err(e) = interests {
+            let _ = syscall!(close(waker_fd));
+            let _ = syscall!(close(epoll_fd));
+            return Err(io_error(e));
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut events: Vec<epoll_event> = Vec::with_capacity(2);
+            let result = (|| loop {
+                // messages could have arrived before the interest was added
+                if !drain(&channel, &mut sink, &thread_stop)? || thread_stop.load(Ordering::SeqCst)
+                {
+                    return Ok(());
+                }
+                let ready = match syscall!(epoll_wait(epoll_fd, events.as_mut_ptr(), 2, -1)) {
+                    Ok(ready) => ready,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => 0,
+                    Err(e) => return Err(io_error(e)),
+                };
+                // why: epoll_wait initialized the first `ready` events
+                unsafe { events.set_len(ready as usize) };
+                if events.iter().any(|event| event.u64 == EPOLL_WAKER_TOKEN) {
+                    return Ok(());
+                }
+            })();
+            let _ = remove_interest(epoll_fd, channel_fd);
+            let _ = syscall!(close(epoll_fd));
+            result
+        });
+
+        // why: the waker is dropped after the receive thread was joined, so is the eventfd
+        let waker = FdGuard(waker_fd);
+        Ok(NotifierHandle {
+            stop,
+            waker: Box::new(move || {
+                let one: u64 = 1;
+                let _ = syscall!(write(
+                    waker.as_raw_fd(),
+                    &one as *const u64 as *const libc::c_void,
+                    8
+                ));
+            }),
+            thread: Some(thread),
+        })
+    }
+}
+
+// close the descriptor on drop
+struct FdGuard(RawFd);
+
+impl AsRawFd for FdGuard {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for FdGuard {
+    fn drop(&mut self) {
+        let _ = syscall!(close(self.0));
+    }
+}
+
+/// ask the kernel to raise SIGIO when a message arrives and read on a signal-hook thread
+/// SIGIO is process wide, every SIGIO strategy drains its channel on every signal
+pub struct SigioStrategy;
+
+impl<C> NotificationStrategy<C> for SigioStrategy
+where
+    C: AbstractRPMsgChannel + AsRawFd + Send + 'static,
+{
+    fn start(
+        self: Box<Self>,
+        channel: SharedChannel<C>,
+        mut sink: MessageSink,
+    ) -> Result<NotifierHandle, ChannelError> {
+        let channel_fd = channel.lock().unwrap().as_raw_fd();
+        // why: register the handler first, the default action of SIGIO terminates the process
+        let mut signals = Signals::new([SIGIO]).map_err(io_error)?;
+        let signal_handle = signals.handle();
+        syscall!(fcntl(channel_fd, F_SETOWN, getpid())).map_err(io_error)?; // Tell the kernel to whom to send the signal
+        let current_flags = syscall!(fcntl(channel_fd, F_GETFL)).map_err(io_error)?;
+        syscall!(fcntl(channel_fd, F_SETFL, current_flags | O_ASYNC)).map_err(io_error)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            let result = (|| {
+                // messages could have arrived before O_ASYNC was set
+                if !drain(&channel, &mut sink, &thread_stop)? {
+                    return Ok(());
+                }
+                for _ in signals.forever() {
+                    if thread_stop.load(Ordering::SeqCst)
+                        || !drain(&channel, &mut sink, &thread_stop)?
+                    {
+                        break;
+                    }
+                }
+                Ok(())
+            })();
+            if let Ok(flags) = syscall!(fcntl(channel_fd, F_GETFL)) {
+                if let Err(e) = syscall!(fcntl(channel_fd, F_SETFL, flags & !O_ASYNC)) {
+                    error!("can't turn off O_ASYNC: {}", e);
+                }
+            }
+            result
+        });
+
+        Ok(NotifierHandle {
+            stop,
+            waker: Box::new(move || signal_handle.close()),
+            thread: Some(thread),
+        })
+    }
+}
+
+/// block in poll(2) on the channel, waking up regularly to check whether it should stop
+pub struct BlockingStrategy;
+
+impl<C> NotificationStrategy<C> for BlockingStrategy
+where
+    C: AbstractRPMsgChannel + AsRawFd + Send + 'static,
+{
+    fn start(
+        self: Box<Self>,
+        channel: SharedChannel<C>,
+        mut sink: MessageSink,
+    ) -> Result<NotifierHandle, ChannelError> {
+        let channel_fd = channel.lock().unwrap().as_raw_fd();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || loop {
+            if !drain(&channel, &mut sink, &thread_stop)? || thread_stop.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+            let mut fds = [PollFd::new(channel_fd, PollFlags::POLLIN)];
+            match poll(&mut fds, BLOCKING_POLL_INTERVAL_MS) {
+                Ok(_) | Err(Errno::EINTR) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        });
+
+        Ok(NotifierHandle {
+            stop,
+            waker: Box::new(|| {}),
+            thread: Some(thread),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::LoopbackRPMsgChannel;
+    use std::sync::mpsc::channel;
+    use std::time::Instant;
+
+    fn deliver_with(kind: NotificationKind) {
+        let (host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let host = Arc::new(Mutex::new(host));
+        let (tx, rx) = channel();
+        // a message pending before the strategy starts is not lost
+        remote.send(b"early").unwrap();
+        let handle = kind
+            .strategy()
+            .start(host.clone(), MessageSink::Channel(tx))
+            .unwrap();
+        for i in 0..3u8 {
+            remote.send(&[i; 4]).unwrap();
+        }
+        let timeout = Duration::from_secs(5);
+        assert_eq!(rx.recv_timeout(timeout).unwrap(), b"early");
+        for i in 0..3u8 {
+            assert_eq!(rx.recv_timeout(timeout).unwrap(), vec![i; 4]);
+        }
+        // the sender side of the channel is still usable
+        host.lock().unwrap().send(b"ping").unwrap();
+        assert_eq!(remote.read(16).unwrap(), b"ping");
+        assert_eq!(handle.stop(), Ok(()));
+    }
+
+    #[test]
+    fn epoll_delivers_messages() {
+        deliver_with(NotificationKind::Epoll);
+    }
+
+    #[test]
+    fn sigio_delivers_messages() {
+        deliver_with(NotificationKind::Sigio);
+    }
+
+    #[test]
+    fn blocking_delivers_messages() {
+        deliver_with(NotificationKind::Blocking);
+    }
+
+    fn stop_on_hang_up(kind: NotificationKind) {
+        let (host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let pool = BufferPool::new(2, MAX_RPMSG_BUFF_SIZE as usize);
+        let delivered = Arc::new(AtomicBool::new(false));
+        let sinks = [
+            MessageSink::Callback(Box::new({
+                let delivered = delivered.clone();
+                move |_| delivered.store(true, Ordering::SeqCst)
+            })),
+            MessageSink::Leased(
+                pool.clone(),
+                Box::new({
+                    let delivered = delivered.clone();
+                    move |_| delivered.store(true, Ordering::SeqCst)
+                }),
+            ),
+        ];
+        let host = Arc::new(Mutex::new(host));
+        let handles: Vec<NotifierHandle> = sinks
+            .into_iter()
+            .map(|sink| kind.strategy().start(host.clone(), sink).unwrap())
+            .collect();
+        drop(remote);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !handles.iter().all(NotifierHandle::is_finished) {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "{} kept receiving",
+                kind
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!delivered.load(Ordering::SeqCst));
+        assert_eq!(pool.available(), 2);
+        for handle in handles {
+            assert_eq!(handle.stop(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn hang_up_stops_the_strategies() {
+        stop_on_hang_up(NotificationKind::Epoll);
+        stop_on_hang_up(NotificationKind::Sigio);
+        stop_on_hang_up(NotificationKind::Blocking);
+    }
+
+    fn skip_bad_message(kind: NotificationKind) {
+        let (tx, rx) = channel();
+        // the channel sink reads any message, only the leased buffers are too small
+        let sinks = [
+            (MessageSink::Channel(tx.clone()), false),
+            (
+                MessageSink::Leased(
+                    BufferPool::new(2, 8),
+                    Box::new(move |message| tx.send(message.to_vec()).unwrap()),
+                ),
+                true,
+            ),
+        ];
+        for (sink, small_buffers) in sinks {
+            let (mut host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+            host.set_checksum(true);
+            let host = Arc::new(Mutex::new(host));
+            let handle = kind.strategy().start(host.clone(), sink).unwrap();
+            // no checksum, then a message too large for the buffers, then a good one
+            remote.send(b"corrupted").unwrap();
+            remote.set_checksum(true);
+            if small_buffers {
+                remote.send(b"too large for the pool").unwrap();
+            }
+            remote.send(b"good").unwrap();
+            assert_eq!(
+                rx.recv_timeout(Duration::from_secs(5)).as_deref(),
+                Ok(&b"good"[..]),
+                "{} stopped on a bad message",
+                kind
+            );
+            assert!(!handle.is_finished());
+            let stats = host.lock().unwrap().stats();
+            assert_eq!(stats.checksum_mismatches, 1);
+            assert_eq!(stats.overflows, small_buffers as u64);
+            assert_eq!(handle.stop(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn bad_message_doesnt_stop_the_strategies() {
+        skip_bad_message(NotificationKind::Epoll);
+        skip_bad_message(NotificationKind::Sigio);
+        skip_bad_message(NotificationKind::Blocking);
+    }
+
+    #[test]
+    fn callback_sink() {
+        let (host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let (tx, rx) = channel();
+        let handle = NotificationKind::Epoll
+            .strategy()
+            .start(
+                Arc::new(Mutex::new(host)),
+                MessageSink::Callback(Box::new(move |message| tx.send(message.len()).unwrap())),
+            )
+            .unwrap();
+        remote.send(&[0; 10]).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(10));
+        drop(handle);
+    }
+
+    #[test]
+    fn leased_sink() {
+        let (host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let pool = BufferPool::new(4, MAX_RPMSG_BUFF_SIZE as usize);
+        let (tx, rx) = channel();
+        let handle = NotificationKind::Epoll
+            .strategy()
+            .start(
+                Arc::new(Mutex::new(host)),
+                MessageSink::Leased(
+                    pool.clone(),
+                    Box::new(move |message| tx.send(message).unwrap()),
+                ),
+            )
+            .unwrap();
+        remote.send(b"leased").unwrap();
+        let message = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(&*message, b"leased");
+        assert_eq!(pool.available(), 3);
+        drop(message);
+        assert_eq!(pool.available(), 4);
+        drop(handle);
+    }
+
+    #[test]
+    fn leased_sink_waits_for_buffers() {
+        let (host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let pool = BufferPool::new(1, MAX_RPMSG_BUFF_SIZE as usize);
+        let (tx, rx) = channel();
+        let handle = NotificationKind::Epoll
+            .strategy()
+            .start(
+                Arc::new(Mutex::new(host)),
+                MessageSink::Leased(
+                    pool.clone(),
+                    Box::new(move |message| tx.send(message).unwrap()),
+                ),
+            )
+            .unwrap();
+        for i in 0..3u8 {
+            remote.send(&[i; 4]).unwrap();
+        }
+        let timeout = Duration::from_secs(5);
+        let first = rx.recv_timeout(timeout).unwrap();
+        assert_eq!(&*first, [0; 4]);
+        // why: the only buffer is held, the next messages wait in the channel
+        assert!(rx.recv_timeout(Duration::from_millis(30)).is_err());
+        assert!(!handle.is_finished());
+        drop(first);
+        assert_eq!(&*rx.recv_timeout(timeout).unwrap(), [1; 4]);
+        assert_eq!(&*rx.recv_timeout(timeout).unwrap(), [2; 4]);
+
+        // a receive thread waiting for a buffer still stops
+        remote.send(b"held").unwrap();
+        remote.send(b"pending").unwrap();
+        let held = rx.recv_timeout(timeout).unwrap();
+        assert_eq!(handle.stop(), Ok(()));
+        drop(held);
+        assert_eq!(pool.available(), 1);
+    }
+
+    #[test]
+    fn parse_kind() {
+        for kind in [
+            NotificationKind::Epoll,
+            NotificationKind::Sigio,
+            NotificationKind::Blocking,
+        ] {
+            assert_eq!(kind.to_string().parse::<NotificationKind>(), Ok(kind));
+        }
+        assert!("select".parse::<NotificationKind>().is_err());
+    }
+}
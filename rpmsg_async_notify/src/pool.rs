@@ -0,0 +1,228 @@
+use crate::channel::{AbstractRPMsgChannel, ChannelError};
+use crossbeam::queue::ArrayQueue;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// # Buffer pool
+/// A fixed number of receive buffers allocated up front.
+/// A buffer is leased to whoever receives a message and goes back to the pool
+/// when the lease is dropped, so the receive path doesn't hit the allocator.
+/// Clones share the same buffers.
+#[derive(Clone)]
+pub struct BufferPool {
+    shared: Arc<PoolShared>,
+    buffer_size: usize,
+}
+
+// the buffers and the condition signalled when one goes back
+struct PoolShared {
+    buffers: ArrayQueue<Box<[u8]>>,
+    returned_lock: Mutex<()>,
+    returned: Condvar,
+}
+
+impl BufferPool {
+    /// allocate count buffers of buffer_size bytes, count must not be 0
+    pub fn new(count: usize, buffer_size: usize) -> Self {
+        let buffers = ArrayQueue::new(count);
+        for _ in 0..count {
+            // why: the queue was created with room for count buffers
+            let _ = buffers.push(vec![0u8; buffer_size].into_boxed_slice());
+        }
+        BufferPool {
+            shared: Arc::new(PoolShared {
+                buffers,
+                returned_lock: Mutex::new(()),
+                returned: Condvar::new(),
+            }),
+            buffer_size,
+        }
+    }
+
+    /// lease a buffer, None when every buffer is leased
+    /// the leased buffer is empty, its capacity is the buffer size of the pool
+    pub fn lease(&self) -> Option<LeasedBuffer> {
+        let buffer = self.shared.buffers.pop()?;
+        Some(LeasedBuffer {
+            buffer: Some(buffer),
+            len: 0,
+            pool: self.shared.clone(),
+        })
+    }
+
+    /// lease a buffer and read one message from channel into it
+    pub fn receive<C: AbstractRPMsgChannel>(
+        &self,
+        channel: &mut C,
+    ) -> Result<LeasedBuffer, ChannelError> {
+        let mut buffer = self.lease().ok_or(ChannelError::BufferPoolExhausted {
+            capacity: self.capacity(),
+        })?;
+        let size = channel.read_into(buffer.spare_capacity())?;
+        buffer.set_len(size);
+        Ok(buffer)
+    }
+
+    /// wait up to timeout until a buffer is not leased, false if none came back in time
+    pub fn wait_available(&self, timeout: Duration) -> bool {
+        let guard = self.shared.returned_lock.lock().unwrap();
+        let (_guard, result) = self
+            .shared
+            .returned
+            .wait_timeout_while(guard, timeout, |_| self.shared.buffers.is_empty())
+            .unwrap();
+        !result.timed_out()
+    }
+
+    /// the number of buffers which are not leased
+    pub fn available(&self) -> usize {
+        self.shared.buffers.len()
+    }
+
+    /// the number of buffers of the pool
+    pub fn capacity(&self) -> usize {
+        self.shared.buffers.capacity()
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+}
+
+impl fmt::Debug for BufferPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferPool")
+            .field("available", &self.available())
+            .field("capacity", &self.capacity())
+            .field("buffer_size", &self.buffer_size)
+            .finish()
+    }
+}
+
+/// a buffer of a pool, it dereferences to the received message
+/// and goes back to the pool on drop
+pub struct LeasedBuffer {
+    // only taken away by drop
+    buffer: Option<Box<[u8]>>,
+    len: usize,
+    pool: Arc<PoolShared>,
+}
+
+impl LeasedBuffer {
+    /// the whole buffer, to read a message into
+    pub fn spare_capacity(&mut self) -> &mut [u8] {
+        self.buffer_mut()
+    }
+
+    /// set the size of the message in the buffer, at most the buffer size
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.capacity(), "message larger than the buffer");
+        self.len = len;
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer().len()
+    }
+
+    fn buffer(&self) -> &[u8] {
+        self.buffer
+            .as_ref()
+            .expect("buffer is only taken away on drop")
+    }
+
+    fn buffer_mut(&mut self) -> &mut [u8] {
+        self.buffer
+            .as_mut()
+            .expect("buffer is only taken away on drop")
+    }
+}
+
+impl Deref for LeasedBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer()[..self.len]
+    }
+}
+
+impl DerefMut for LeasedBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        let len = self.len;
+        &mut self.buffer_mut()[..len]
+    }
+}
+
+impl fmt::Debug for LeasedBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LeasedBuffer")
+            .field("message", &self.deref())
+            .finish()
+    }
+}
+
+impl Drop for LeasedBuffer {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            // why: the buffer came from this queue, so there is room for it
+            let _ = self.pool.buffers.push(buffer);
+            // why: notify under the lock, a waiter checks the queue under it and can't miss this
+            let _guard = self.pool.returned_lock.lock().unwrap();
+            self.pool.returned.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::LoopbackRPMsgChannel;
+    use crate::MAX_RPMSG_BUFF_SIZE;
+
+    #[test]
+    fn buffers_go_back_on_drop() {
+        let pool = BufferPool::new(2, 16);
+        let first = pool.lease().unwrap();
+        let second = pool.lease().unwrap();
+        assert!(first.is_empty());
+        assert_eq!(second.capacity(), 16);
+        assert!(pool.lease().is_none());
+        assert_eq!(pool.available(), 0);
+        drop(first);
+        assert_eq!(pool.available(), 1);
+        let shared = pool.clone();
+        drop(second);
+        assert_eq!(shared.available(), 2);
+    }
+
+    #[test]
+    fn receive_into_leased_buffers() {
+        let (mut host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let pool = BufferPool::new(1, MAX_RPMSG_BUFF_SIZE as usize);
+        remote.send(b"first").unwrap();
+        remote.send(b"second").unwrap();
+        let message = pool.receive(&mut host).unwrap();
+        assert_eq!(&*message, b"first");
+        assert_eq!(
+            pool.receive(&mut host).unwrap_err(),
+            ChannelError::BufferPoolExhausted { capacity: 1 }
+        );
+        drop(message);
+        assert_eq!(&*pool.receive(&mut host).unwrap(), b"second");
+    }
+
+    #[test]
+    fn wait_for_a_returned_buffer() {
+        let pool = BufferPool::new(1, 16);
+        assert!(pool.wait_available(Duration::ZERO));
+        let leased = pool.lease().unwrap();
+        assert!(!pool.wait_available(Duration::from_millis(5)));
+        let returner = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            drop(leased);
+        });
+        assert!(pool.wait_available(Duration::from_secs(5)));
+        returner.join().unwrap();
+    }
+}
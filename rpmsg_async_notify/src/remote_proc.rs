@@ -1,11 +1,23 @@
+use crate::firmware::{sha256_hex, ElfError, FirmwareImage};
+use crate::sysfs::SysfsRoot;
+use log::{trace, warn};
 use nix::fcntl::{open, OFlag};
 use nix::sys::stat::Mode;
 use nix::unistd::write;
 use snafu::{ResultExt, Snafu};
-use std::fs::{File, OpenOptions};
-use std::io;
-use std::os::unix::prelude::FileExt;
-use std::path::Path;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::prelude::{AsRawFd, FileExt, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// how often wait_for_state reads the state, the kernel doesn't notify changes of it
+const STATE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
 #[derive(Debug, Snafu)]
 #[snafu(visibility = "pub")] // Sets the default visibility for these context selectors
 pub enum RemoteprocManagerError {
@@ -20,25 +32,182 @@ pub enum RemoteprocManagerError {
         operation: String,
         source: nix::Error,
     },
+
+    #[snafu(display("can't read the state from {}, error {}", path, source))]
+    FailedToReadState { path: String, source: io::Error },
+
+    #[snafu(display("can't read directory {}, error {}", path, source))]
+    FailedToReadDir { path: String, source: io::Error },
+
+    #[snafu(display("can't read firmware {}, error {}", path, source))]
+    FailedToReadFirmware { path: String, source: io::Error },
+
+    #[snafu(display("{} is not a remoteproc firmware, {}", path, source))]
+    InvalidFirmware { path: String, source: ElfError },
+
+    #[snafu(display("can't deploy firmware to {}, error {}", path, source))]
+    FailedToDeployFirmware { path: String, source: io::Error },
+
+    /// the firmware file was replaced since it was deployed
+    #[snafu(display(
+        "firmware {} has SHA-256 {}, expected {}",
+        firmware_name,
+        actual,
+        expected
+    ))]
+    FirmwareDigestMismatch {
+        firmware_name: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[snafu(display("unknown remoteproc state {}", state))]
+    UnknownState { state: String },
+
+    /// the kernel would refuse the operation in this state
+    #[snafu(display("can't {} a remoteproc which is {}", operation, state))]
+    InvalidTransition {
+        operation: String,
+        state: RemoteprocState,
+    },
+
+    #[snafu(display(
+        "remoteproc still {} after {:?}, expected {}",
+        actual,
+        timeout,
+        expected
+    ))]
+    StateTimeout {
+        expected: RemoteprocState,
+        actual: RemoteprocState,
+        timeout: Duration,
+    },
+}
+
+/// the state of a remote processor, as the kernel reports it in the state file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RemoteprocState {
+    Offline,
+    Suspended,
+    Running,
+    /// the firmware crashed, recovery restarts it unless recovery is disabled
+    Crashed,
+    /// the remoteproc is being removed
+    Deleted,
+    /// running a firmware started by someone else, e.g. the bootloader
+    Attached,
+    /// still running, but no longer controlled by the kernel
+    Detached,
+    Invalid,
+}
+
+impl RemoteprocState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RemoteprocState::Offline => "offline",
+            RemoteprocState::Suspended => "suspended",
+            RemoteprocState::Running => "running",
+            RemoteprocState::Crashed => "crashed",
+            RemoteprocState::Deleted => "deleted",
+            RemoteprocState::Attached => "attached",
+            RemoteprocState::Detached => "detached",
+            RemoteprocState::Invalid => "invalid",
+        }
+    }
+
+    /// start boots an offline remoteproc and attaches to a detached one
+    pub fn can_start(&self) -> bool {
+        matches!(self, RemoteprocState::Offline | RemoteprocState::Detached)
+    }
+
+    /// stop shuts down the firmware, whether it runs, is attached or crashed
+    pub fn can_stop(&self) -> bool {
+        matches!(
+            self,
+            RemoteprocState::Running | RemoteprocState::Attached | RemoteprocState::Crashed
+        )
+    }
+}
+
+impl FromStr for RemoteprocState {
+    type Err = RemoteprocManagerError;
+
+    fn from_str(state: &str) -> Result<Self, Self::Err> {
+        match state.trim() {
+            "offline" => Ok(RemoteprocState::Offline),
+            "suspended" => Ok(RemoteprocState::Suspended),
+            "running" => Ok(RemoteprocState::Running),
+            "crashed" => Ok(RemoteprocState::Crashed),
+            "deleted" => Ok(RemoteprocState::Deleted),
+            "attached" => Ok(RemoteprocState::Attached),
+            "detached" => Ok(RemoteprocState::Detached),
+            "invalid" => Ok(RemoteprocState::Invalid),
+            _ => Err(RemoteprocManagerError::UnknownState {
+                state: state.trim().to_string(),
+            }),
+        }
+    }
 }
+
+impl fmt::Display for RemoteprocState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// a remote processor registered on the system
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteprocInfo {
+    /// e.g. `remoteproc0`, the index depends on the probe order
+    pub id: String,
+    /// the name given by the device tree, e.g. `r5f_0`
+    pub name: String,
+    pub state: RemoteprocState,
+    /// the firmware loaded by the next start
+    pub firmware: String,
+    /// whether the kernel restarts a crashed firmware, None when the kernel doesn't tell
+    pub recovery: Option<bool>,
+    /// `disabled`, `enabled` or `inline`, None when the kernel doesn't tell
+    pub coredump: Option<String>,
+}
+
+/// a firmware copied to the firmware directory by deploy_firmware
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeployedFirmware {
+    /// the name written to the firmware attribute
+    pub name: String,
+    /// the SHA-256 of the firmware in lowercase hex, start checks it before booting
+    pub sha256: String,
+}
+
 /// the manager of remote processor
 /// the processor it manages is identified by remoteproc_id
 /// the system that runs this manager should support remoteproc
 pub struct RemoteprocManager {
     firmware_path_str: String,
     state_path_str: String,
+    firmware_dir: PathBuf,
+    // the firmware deploy_firmware loaded last, forgotten when another one is loaded
+    deployed: Mutex<Option<DeployedFirmware>>,
 }
 impl RemoteprocManager {
     /// initialize the remoteproc manager for remoteproc_id
     pub fn new(remoteproc_id: &str) -> Result<Self, io::Error> {
-        let firmware_path_str = format!("/sys/class/remoteproc/{}/firmware", remoteproc_id);
-        let firmware_path = Path::new(&firmware_path_str);
-        let state_path_str = format!("/sys/class/remoteproc/{}/state", remoteproc_id);
-        let state_path = Path::new(&state_path_str);
+        RemoteprocManager::new_in(&SysfsRoot::default(), remoteproc_id)
+    }
+    /// same as new, with sysfs located under sysfs
+    pub fn new_in(sysfs: &SysfsRoot, remoteproc_id: &str) -> Result<Self, io::Error> {
+        let remoteproc_dir = sysfs.remoteproc_dir(remoteproc_id);
+        let firmware_path = remoteproc_dir.join("firmware");
+        let state_path = remoteproc_dir.join("state");
         if firmware_path.exists() & state_path.exists() {
+            let firmware_path_str = firmware_path.display().to_string();
+            let state_path_str = state_path.display().to_string();
             Ok(RemoteprocManager {
                 firmware_path_str,
                 state_path_str,
+                firmware_dir: sysfs.firmware_dir(),
+                deployed: Mutex::new(None),
             })
         } else {
             Err(io::Error::new(
@@ -47,9 +216,82 @@ impl RemoteprocManager {
             ))
         }
     }
+    /// initialize the remoteproc manager for the remote processor called name
+    pub fn by_name(name: &str) -> Result<Self, io::Error> {
+        RemoteprocManager::by_name_in(&SysfsRoot::default(), name)
+    }
+    /// same as by_name, with sysfs located under sysfs
+    pub fn by_name_in(sysfs: &SysfsRoot, name: &str) -> Result<Self, io::Error> {
+        let remoteprocs =
+            RemoteprocManager::list_in(sysfs).map_err(|e| io::Error::other(e.to_string()))?;
+        match remoteprocs
+            .iter()
+            .find(|remoteproc| remoteproc.name == name)
+        {
+            Some(remoteproc) => RemoteprocManager::new_in(sysfs, &remoteproc.id),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("don't find a remoteproc called {} on the platform", name),
+            )),
+        }
+    }
+    /// list the remote processors of the system, sorted by id
+    /// an empty list is returned when remoteproc is not supported,
+    /// a remoteproc whose state can't be read or is unknown is listed as invalid
+    pub fn list() -> Result<Vec<RemoteprocInfo>, RemoteprocManagerError> {
+        RemoteprocManager::list_in(&SysfsRoot::default())
+    }
+    /// same as list, with sysfs located under sysfs
+    pub fn list_in(sysfs: &SysfsRoot) -> Result<Vec<RemoteprocInfo>, RemoteprocManagerError> {
+        let class_dir = sysfs.remoteproc_class_dir();
+        if !class_dir.exists() {
+            return Ok(Vec::new());
+        }
+        let entries = fs::read_dir(&class_dir).context(FailedToReadDir {
+            path: class_dir.display().to_string(),
+        })?;
+        let mut remoteprocs = Vec::new();
+        for entry in entries.flatten() {
+            let id = entry.file_name().to_string_lossy().into_owned();
+            let dir = entry.path();
+            let attribute = |name: &str| {
+                fs::read_to_string(dir.join(name))
+                    .ok()
+                    .map(|value| value.trim().to_string())
+            };
+            let state_path = dir.join("state");
+            // why: one broken remoteproc must not hide the others, the kernel also reports
+            // a state it doesn't know as invalid
+            let state = match fs::read_to_string(&state_path)
+                .context(FailedToReadState {
+                    path: state_path.display().to_string(),
+                })
+                .and_then(|state| state.parse())
+            {
+                Ok(state) => state,
+                Err(e) => {
+                    warn!("listing {} as invalid, {}", id, e);
+                    RemoteprocState::Invalid
+                }
+            };
+            remoteprocs.push(RemoteprocInfo {
+                name: attribute("name").unwrap_or_default(),
+                state,
+                firmware: attribute("firmware").unwrap_or_default(),
+                recovery: attribute("recovery").map(|recovery| recovery == "enabled"),
+                coredump: attribute("coredump"),
+                id,
+            });
+        }
+        // why: remoteproc10 goes after remoteproc9
+        remoteprocs.sort_by(|a, b| (a.id.len(), &a.id).cmp(&(b.id.len(), &b.id)));
+        Ok(remoteprocs)
+    }
     pub fn load_firmware_rs(&self, firmware_name: String) -> Result<(), io::Error> {
+        self.forget_deployed();
         let fd = OpenOptions::new()
             .write(true)
+            .truncate(true)
             .open(&self.firmware_path_str)?;
 
         fd.write_all_at(firmware_name.as_bytes(), 0)?;
@@ -58,16 +300,19 @@ impl RemoteprocManager {
 
     /// load specific firmware on the remoteproc
     pub fn load_firmware(&self, firmware_name: String) -> Result<(), RemoteprocManagerError> {
+        self.forget_deployed();
         let firmware_buf = firmware_name.clone().into_bytes();
         let fd = open(
             Path::new(&self.firmware_path_str),
-            OFlag::O_RDWR,
+            OFlag::O_RDWR | OFlag::O_TRUNC,
             Mode::empty(),
         )
         .context(FailedToLoadFirmware {
             firmware_name: firmware_name.clone(),
         })?;
-        let size = write(fd, &firmware_buf).context(FailedToLoadFirmware {
+        // why: open returned a new descriptor which nobody else owns, it is closed on drop
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let size = write(fd.as_raw_fd(), &firmware_buf).context(FailedToLoadFirmware {
             firmware_name: firmware_name.clone(),
         })?;
         if size as usize == firmware_buf.len() {
@@ -79,61 +324,498 @@ impl RemoteprocManager {
             })
         }
     }
+    /// copy the ELF at path to the firmware directory and load it on the remoteproc
+    /// the ELF has to be a 32-bit ARM executable with a resource table,
+    /// the copy replaces a firmware of the same name at once, so the kernel never
+    /// sees a half written file
+    /// the remoteproc has to be offline, the kernel refuses a new firmware otherwise,
+    /// and start then checks the file still has the SHA-256 of the deployed one
+    pub fn deploy_firmware(&self, path: &Path) -> Result<DeployedFirmware, RemoteprocManagerError> {
+        let path_str = path.display().to_string();
+        let image = fs::read(path).context(FailedToReadFirmware {
+            path: path_str.clone(),
+        })?;
+        FirmwareImage::parse(&image)
+            .and_then(|firmware| firmware.validate())
+            .context(InvalidFirmware {
+                path: path_str.clone(),
+            })?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| RemoteprocManagerError::FailedToReadFirmware {
+                path: path_str,
+                source: io::Error::new(io::ErrorKind::InvalidInput, "not a file name"),
+            })?;
+        // why: the file of a running firmware must not be replaced by a deploy the kernel refuses
+        let state = self.state()?;
+        if state != RemoteprocState::Offline {
+            return Err(RemoteprocManagerError::InvalidTransition {
+                operation: "deploy firmware to".to_string(),
+                state,
+            });
+        }
+        let destination = self.firmware_dir.join(&name);
+        let staging = self.firmware_dir.join(format!(".{}.deploy", name));
+        let copy = || -> io::Result<()> {
+            let mut file = File::create(&staging)?;
+            file.write_all(&image)?;
+            // why: the rename can reach the disk before the data does, sync the data first
+            file.sync_all()?;
+            fs::rename(&staging, &destination)
+        };
+        if let Err(e) = copy() {
+            let _ = fs::remove_file(&staging);
+            return Err(RemoteprocManagerError::FailedToDeployFirmware {
+                path: destination.display().to_string(),
+                source: e,
+            });
+        }
+        let firmware = DeployedFirmware {
+            name,
+            sha256: sha256_hex(&image),
+        };
+        trace!(
+            "deployed {} with SHA-256 {}",
+            destination.display(),
+            firmware.sha256
+        );
+        self.load_firmware(firmware.name.clone())?;
+        *self.deployed.lock().unwrap() = Some(firmware.clone());
+        Ok(firmware)
+    }
+
+    fn forget_deployed(&self) {
+        self.deployed.lock().unwrap().take();
+    }
+
+    /// the SHA-256 of the firmware file the next start loads
+    pub fn firmware_sha256(&self) -> Result<String, RemoteprocManagerError> {
+        let (_, sha256) = self.loaded_firmware()?;
+        Ok(sha256)
+    }
+
+    // the name in the firmware attribute and the SHA-256 of the file it names
+    fn loaded_firmware(&self) -> Result<(String, String), RemoteprocManagerError> {
+        let name = fs::read_to_string(&self.firmware_path_str)
+            .context(FailedToReadFirmware {
+                path: self.firmware_path_str.clone(),
+            })?
+            .trim()
+            .to_string();
+        let path = self.firmware_dir.join(&name);
+        let image = fs::read(&path).context(FailedToReadFirmware {
+            path: path.display().to_string(),
+        })?;
+        Ok((name, sha256_hex(&image)))
+    }
+
+    /// the current state of the remoteproc
+    pub fn state(&self) -> Result<RemoteprocState, RemoteprocManagerError> {
+        fs::read_to_string(&self.state_path_str)
+            .context(FailedToReadState {
+                path: self.state_path_str.clone(),
+            })?
+            .parse()
+    }
+
+    /// read the state until it is expected, return StateTimeout if it isn't within timeout
+    pub fn wait_for_state(
+        &self,
+        expected: RemoteprocState,
+        timeout: Duration,
+    ) -> Result<(), RemoteprocManagerError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let actual = self.state()?;
+            if actual == expected {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(RemoteprocManagerError::StateTimeout {
+                    expected,
+                    actual,
+                    timeout,
+                });
+            }
+            sleep(STATE_POLL_INTERVAL);
+        }
+    }
+
+    /// write start to the state file, without checking the state
+    /// nor the digest of a deployed firmware first
     pub fn start_rs(&self) -> Result<(), io::Error> {
-        let fd = OpenOptions::new().write(true).open(&self.state_path_str)?;
+        let fd = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&self.state_path_str)?;
 
         fd.write_all_at("start".as_bytes(), 0)?;
         Ok(())
     }
-    /// start remoteproc
+    /// start remoteproc, it has to be offline or detached
+    /// after deploy_firmware, the firmware file has to still have the deployed SHA-256
     pub fn start(&self) -> Result<(), RemoteprocManagerError> {
-        let command = String::from("start").into_bytes();
-        let fd = open(
-            Path::new(&self.state_path_str),
-            OFlag::O_RDWR | OFlag::O_SYNC,
-            Mode::empty(),
-        )
-        .context(FailedToOperateFirmware {
-            operation: "start".to_string(),
-        })?;
-        let size = write(fd, &command).context(FailedToOperateFirmware {
-            operation: "start".to_string(),
-        })?;
-        if size as usize == command.len() {
-            Ok(())
-        } else {
-            Err(RemoteprocManagerError::FailedToOperateFirmware {
+        let deployed = self.deployed.lock().unwrap().clone();
+        if let Some(deployed) = deployed {
+            self.check_firmware(&deployed.sha256)?;
+        }
+        self.start_checked()
+    }
+    /// start remoteproc like start, if its firmware file has the SHA-256 expected_sha256
+    /// the digest is checked right before the start, a firmware replaced since it was
+    /// deployed is refused with FirmwareDigestMismatch
+    pub fn start_verified(&self, expected_sha256: &str) -> Result<(), RemoteprocManagerError> {
+        self.check_firmware(expected_sha256)?;
+        self.start_checked()
+    }
+
+    // start once the state allows it
+    fn start_checked(&self) -> Result<(), RemoteprocManagerError> {
+        let state = self.state()?;
+        if !state.can_start() {
+            return Err(RemoteprocManagerError::InvalidTransition {
                 operation: "start".to_string(),
-                source: nix::Error::EINTR,
-            })
+                state,
+            });
         }
+        self.write_state("start")
     }
+
+    // FirmwareDigestMismatch unless the firmware file has the SHA-256 expected_sha256
+    fn check_firmware(&self, expected_sha256: &str) -> Result<(), RemoteprocManagerError> {
+        let (firmware_name, actual) = self.loaded_firmware()?;
+        if !actual.eq_ignore_ascii_case(expected_sha256.trim()) {
+            return Err(RemoteprocManagerError::FirmwareDigestMismatch {
+                firmware_name,
+                expected: expected_sha256.trim().to_lowercase(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+    /// write stop to the state file, without checking the state first
     pub fn stop_rs(&self) -> Result<(), io::Error> {
-        let fd = OpenOptions::new().write(true).open(&self.state_path_str)?;
+        let fd = OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&self.state_path_str)?;
         fd.write_all_at("stop".as_bytes(), 0)?;
         Ok(())
     }
-    /// stop the remoteproc
+    /// stop the remoteproc, it has to be running, attached or crashed
     pub fn stop(&self) -> Result<(), RemoteprocManagerError> {
-        let command = String::from("stop").into_bytes();
+        let state = self.state()?;
+        if !state.can_stop() {
+            return Err(RemoteprocManagerError::InvalidTransition {
+                operation: "stop".to_string(),
+                state,
+            });
+        }
+        self.write_state("stop")
+    }
+
+    // write command to the state file
+    // truncated like a shell redirection does, sysfs ignores it but a fake tree needs it
+    fn write_state(&self, command: &str) -> Result<(), RemoteprocManagerError> {
+        trace!("writing {} to {}", command, self.state_path_str);
         let fd = open(
             Path::new(&self.state_path_str),
-            OFlag::O_RDWR | OFlag::O_SYNC,
+            OFlag::O_RDWR | OFlag::O_SYNC | OFlag::O_TRUNC,
             Mode::empty(),
         )
         .context(FailedToOperateFirmware {
-            operation: "stop".to_string(),
+            operation: command.to_string(),
         })?;
-        let size = write(fd, &command).context(FailedToOperateFirmware {
-            operation: "stop".to_string(),
+        // why: open returned a new descriptor which nobody else owns, it is closed on drop
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let size = write(fd.as_raw_fd(), command.as_bytes()).context(FailedToOperateFirmware {
+            operation: command.to_string(),
         })?;
-        if size as usize == command.len() {
+        if size == command.len() {
             Ok(())
         } else {
             Err(RemoteprocManagerError::FailedToOperateFirmware {
-                operation: "stop".to_string(),
+                operation: command.to_string(),
                 source: nix::Error::EINTR,
             })
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firmware::tests::fake_firmware;
+    use crate::firmware::RESOURCE_TABLE_SECTION;
+    use crate::remote_proc_sim::{RemoteprocFault, RemoteprocSimulator};
+    use nix::errno::Errno;
+
+    fn simulated_remoteproc(root: &Path) -> (RemoteprocManager, RemoteprocSimulator) {
+        let sysfs = SysfsRoot::new(root);
+        let simulator = RemoteprocSimulator::new(&sysfs, "remoteproc0").unwrap();
+        simulator.install_firmware("echo_test.elf", b"elf").unwrap();
+        let manager = RemoteprocManager::new_in(&sysfs, "remoteproc0").unwrap();
+        (manager, simulator)
+    }
+
+    #[test]
+    fn start_and_stop() {
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(
+            RemoteprocManager::new_in(&SysfsRoot::new(root.path()), "remoteproc0")
+                .err()
+                .map(|e| e.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+        let (manager, simulator) = simulated_remoteproc(root.path());
+        assert_eq!(manager.state().unwrap(), RemoteprocState::Offline);
+        manager.load_firmware("echo_test.elf".to_string()).unwrap();
+        manager.start().unwrap();
+        simulator.settle().unwrap();
+        assert_eq!(manager.state().unwrap(), RemoteprocState::Running);
+        assert_eq!(simulator.firmware(), "echo_test.elf");
+        assert!(matches!(
+            manager.start(),
+            Err(RemoteprocManagerError::InvalidTransition { state, .. })
+                if state == RemoteprocState::Running
+        ));
+
+        manager.load_firmware_rs("other.elf".to_string()).unwrap();
+        simulator.settle().unwrap();
+        assert_eq!(simulator.take_error(), Some(Errno::EBUSY));
+        assert_eq!(simulator.firmware(), "echo_test.elf");
+
+        manager.stop().unwrap();
+        simulator.settle().unwrap();
+        manager
+            .wait_for_state(RemoteprocState::Offline, Duration::ZERO)
+            .unwrap();
+        assert!(matches!(
+            manager.stop(),
+            Err(RemoteprocManagerError::InvalidTransition { state, .. })
+                if state == RemoteprocState::Offline
+        ));
+        assert_eq!(simulator.take_error(), None);
+    }
+
+    #[test]
+    fn deploy_and_verify_firmware() {
+        let root = tempfile::tempdir().unwrap();
+        let (manager, simulator) = simulated_remoteproc(root.path());
+        let build = tempfile::tempdir().unwrap();
+        let elf_path = build.path().join("echo_test.elf");
+        let elf = fake_firmware(&[(".text", &[0xfe; 64]), (RESOURCE_TABLE_SECTION, &[0; 16])]);
+        fs::write(&elf_path, &elf).unwrap();
+
+        let deployed = manager.deploy_firmware(&elf_path).unwrap();
+        simulator.settle().unwrap();
+        assert_eq!(deployed.name, "echo_test.elf");
+        assert_eq!(deployed.sha256, sha256_hex(&elf));
+        assert_eq!(simulator.firmware(), "echo_test.elf");
+        assert_eq!(
+            fs::read(root.path().join("lib/firmware/echo_test.elf")).unwrap(),
+            elf
+        );
+        assert_eq!(manager.firmware_sha256().unwrap(), deployed.sha256);
+
+        // replaced behind the manager's back
+        simulator
+            .install_firmware(
+                "echo_test.elf",
+                &fake_firmware(&[(RESOURCE_TABLE_SECTION, &[1])]),
+            )
+            .unwrap();
+        assert!(matches!(
+            manager.start_verified(&deployed.sha256),
+            Err(RemoteprocManagerError::FirmwareDigestMismatch { firmware_name, .. })
+                if firmware_name == "echo_test.elf"
+        ));
+        assert!(matches!(
+            manager.start(),
+            Err(RemoteprocManagerError::FirmwareDigestMismatch { .. })
+        ));
+        simulator.settle().unwrap();
+        assert_eq!(manager.state().unwrap(), RemoteprocState::Offline);
+
+        let deployed = manager.deploy_firmware(&elf_path).unwrap();
+        manager
+            .start_verified(&deployed.sha256.to_uppercase())
+            .unwrap();
+        simulator.settle().unwrap();
+        assert_eq!(manager.state().unwrap(), RemoteprocState::Running);
+
+        // the file of the running firmware is left alone
+        let update = build.path().join("echo_test.elf.new");
+        fs::write(&update, fake_firmware(&[(RESOURCE_TABLE_SECTION, &[2])])).unwrap();
+        fs::rename(&update, &elf_path).unwrap();
+        assert!(matches!(
+            manager.deploy_firmware(&elf_path),
+            Err(RemoteprocManagerError::InvalidTransition { state, .. })
+                if state == RemoteprocState::Running
+        ));
+        assert_eq!(
+            fs::read(root.path().join("lib/firmware/echo_test.elf")).unwrap(),
+            elf
+        );
+        manager.stop().unwrap();
+        simulator.settle().unwrap();
+        manager.start().unwrap();
+        simulator.settle().unwrap();
+        assert_eq!(manager.state().unwrap(), RemoteprocState::Running);
+        assert_eq!(simulator.take_error(), None);
+
+        let script = build.path().join("not_firmware.elf");
+        fs::write(
+            &script,
+            "#!/bin/sh
+",
+        )
+        .unwrap();
+        assert!(matches!(
+            manager.deploy_firmware(&script),
+            Err(RemoteprocManagerError::InvalidFirmware { .. })
+        ));
+        let no_table = build.path().join("no_table.elf");
+        fs::write(&no_table, fake_firmware(&[(".text", &[0; 4])])).unwrap();
+        assert!(matches!(
+            manager.deploy_firmware(&no_table),
+            Err(RemoteprocManagerError::InvalidFirmware {
+                source: ElfError::MissingSection { .. },
+                ..
+            })
+        ));
+        assert!(!root.path().join("lib/firmware/no_table.elf").exists());
+        assert!(matches!(
+            manager.deploy_firmware(&build.path().join("missing.elf")),
+            Err(RemoteprocManagerError::FailedToReadFirmware { .. })
+        ));
+    }
+
+    #[test]
+    fn list_remoteprocs() {
+        let root = tempfile::tempdir().unwrap();
+        let sysfs = SysfsRoot::new(root.path());
+        assert_eq!(RemoteprocManager::list_in(&sysfs).unwrap(), Vec::new());
+        let simulators: Vec<RemoteprocSimulator> = ["remoteproc10", "remoteproc2"]
+            .iter()
+            .map(|id| RemoteprocSimulator::new(&sysfs, id).unwrap())
+            .collect();
+        simulators[0]
+            .install_firmware("echo_test.elf", b"elf")
+            .unwrap();
+        fs::remove_file(simulators[1].dir().join("coredump")).unwrap();
+
+        let by_name = RemoteprocManager::by_name_in(&sysfs, "remoteproc10-sim").unwrap();
+        by_name.load_firmware("echo_test.elf".to_string()).unwrap();
+        by_name.start().unwrap();
+        simulators[0].settle().unwrap();
+        assert_eq!(
+            RemoteprocManager::list_in(&sysfs).unwrap(),
+            vec![
+                RemoteprocInfo {
+                    id: "remoteproc2".to_string(),
+                    name: "remoteproc2-sim".to_string(),
+                    state: RemoteprocState::Offline,
+                    firmware: "rproc-remoteproc2-fw".to_string(),
+                    recovery: Some(false),
+                    coredump: None,
+                },
+                RemoteprocInfo {
+                    id: "remoteproc10".to_string(),
+                    name: "remoteproc10-sim".to_string(),
+                    state: RemoteprocState::Running,
+                    firmware: "echo_test.elf".to_string(),
+                    recovery: Some(false),
+                    coredump: Some("enabled".to_string()),
+                },
+            ]
+        );
+        assert_eq!(
+            RemoteprocManager::by_name_in(&sysfs, "r5f_1")
+                .err()
+                .map(|e| e.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+
+        // a broken remoteproc doesn't hide the others
+        let dirs: Vec<PathBuf> = simulators
+            .iter()
+            .map(|sim| sim.dir().to_path_buf())
+            .collect();
+        // why: a running simulator would put its state back
+        drop(simulators);
+        fs::write(dirs[1].join("state"), "rebooting\n").unwrap();
+        fs::remove_file(dirs[0].join("state")).unwrap();
+        let states: Vec<(String, RemoteprocState)> = RemoteprocManager::list_in(&sysfs)
+            .unwrap()
+            .into_iter()
+            .map(|remoteproc| (remoteproc.id, remoteproc.state))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                ("remoteproc2".to_string(), RemoteprocState::Invalid),
+                ("remoteproc10".to_string(), RemoteprocState::Invalid),
+            ]
+        );
+    }
+
+    #[test]
+    fn failures_of_the_remote() {
+        let root = tempfile::tempdir().unwrap();
+        let (manager, simulator) = simulated_remoteproc(root.path());
+        manager.load_firmware("missing.elf".to_string()).unwrap();
+        manager.start().unwrap();
+        simulator.settle().unwrap();
+        assert_eq!(simulator.take_error(), Some(Errno::ENOENT));
+        assert!(matches!(
+            manager.wait_for_state(RemoteprocState::Running, Duration::from_millis(20)),
+            Err(RemoteprocManagerError::StateTimeout { actual, .. })
+                if actual == RemoteprocState::Offline
+        ));
+
+        manager.load_firmware("echo_test.elf".to_string()).unwrap();
+        simulator.set_fault(RemoteprocFault::MissingFirmware);
+        manager.start().unwrap();
+        simulator.settle().unwrap();
+        assert_eq!(manager.state().unwrap(), RemoteprocState::Offline);
+
+        simulator.set_fault(RemoteprocFault::CrashAfterStart(Duration::from_millis(20)));
+        manager.start().unwrap();
+        simulator.settle().unwrap();
+        manager
+            .wait_for_state(RemoteprocState::Crashed, Duration::from_secs(5))
+            .unwrap();
+        manager.stop().unwrap();
+        simulator.settle().unwrap();
+        assert_eq!(manager.state().unwrap(), RemoteprocState::Offline);
+    }
+
+    #[test]
+    fn parse_states() {
+        for state in [
+            RemoteprocState::Offline,
+            RemoteprocState::Suspended,
+            RemoteprocState::Running,
+            RemoteprocState::Crashed,
+            RemoteprocState::Deleted,
+            RemoteprocState::Attached,
+            RemoteprocState::Detached,
+            RemoteprocState::Invalid,
+        ] {
+            assert_eq!(
+                format!("{}\n", state).parse::<RemoteprocState>().unwrap(),
+                state
+            );
+        }
+        assert!(matches!(
+            "booting".parse::<RemoteprocState>(),
+            Err(RemoteprocManagerError::UnknownState { state }) if state == "booting"
+        ));
+        assert!(RemoteprocState::Offline.can_start());
+        assert!(!RemoteprocState::Running.can_start());
+        assert!(RemoteprocState::Running.can_stop());
+        assert!(!RemoteprocState::Offline.can_stop());
+    }
+}
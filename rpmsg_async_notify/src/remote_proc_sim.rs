@@ -0,0 +1,295 @@
+use crate::remote_proc::RemoteprocState;
+use crate::sysfs::SysfsRoot;
+use log::{trace, warn};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use std::fs;
+use std::io;
+use std::os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// how often the simulator checks whether it has to stop or a crash is due
+const SIMULATOR_POLL_INTERVAL: Duration = Duration::from_millis(5);
+/// how long settle waits for the simulator to catch up
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// a failure the simulated remote processor runs into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RemoteprocFault {
+    #[default]
+    None,
+    /// start fails as if the firmware file wasn't found, even when it exists
+    MissingFirmware,
+    /// the firmware crashes this long after it was started
+    CrashAfterStart(Duration),
+}
+
+// what the kernel knows about the remote processor
+#[derive(Debug)]
+struct Remote {
+    state: RemoteprocState,
+    firmware: String,
+    fault: RemoteprocFault,
+    crash_at: Option<Instant>,
+    last_error: Option<Errno>,
+}
+
+/// # Remoteproc simulator
+/// A fake `/sys/class/remoteproc/{id}` which reacts to writes to `state` and `firmware`
+/// the way the kernel does: start boots the firmware named in `firmware` if it is found
+/// in `/lib/firmware`, stop shuts it down, and a write the kernel would refuse
+/// leaves the attributes as they were. The error the kernel would return to such a write
+/// is kept for take_error, the writer itself can't see it.
+/// A crash stays a crash, as with recovery disabled.
+/// Writes are handled by a thread of the simulator, call settle before checking their effect.
+pub struct RemoteprocSimulator {
+    sysfs: SysfsRoot,
+    dir: PathBuf,
+    remote: Arc<Mutex<Remote>>,
+    settle_count: AtomicUsize,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RemoteprocSimulator {
+    /// create an offline remote processor remoteproc_id under sysfs
+    pub fn new(sysfs: &SysfsRoot, remoteproc_id: &str) -> io::Result<Self> {
+        let dir = sysfs.remoteproc_dir(remoteproc_id);
+        fs::create_dir_all(&dir)?;
+        fs::create_dir_all(sysfs.firmware_dir())?;
+        let remote = Remote {
+            state: RemoteprocState::Offline,
+            firmware: format!("rproc-{}-fw", remoteproc_id),
+            fault: RemoteprocFault::None,
+            crash_at: None,
+            last_error: None,
+        };
+        write_attribute(&dir, "name", &format!("{}-sim", remoteproc_id))?;
+        write_attribute(&dir, "state", remote.state.as_str())?;
+        write_attribute(&dir, "firmware", &remote.firmware)?;
+        write_attribute(&dir, "recovery", "disabled")?;
+        write_attribute(&dir, "coredump", "enabled")?;
+
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        // why: Inotify doesn't close its descriptor, the guard moves to the thread and closes it
+        let inotify_guard = unsafe { OwnedFd::from_raw_fd(inotify.as_raw_fd()) };
+        inotify.add_watch(&dir, AddWatchFlags::IN_CLOSE_WRITE)?;
+
+        let remote = Arc::new(Mutex::new(remote));
+        let stop = Arc::new(AtomicBool::new(false));
+        let simulation = Simulation {
+            sysfs: sysfs.clone(),
+            dir: dir.clone(),
+            remote: remote.clone(),
+        };
+        let thread_stop = stop.clone();
+        let thread = thread::spawn(move || {
+            let _inotify_guard = inotify_guard;
+            simulation.run(inotify, &thread_stop)
+        });
+        Ok(RemoteprocSimulator {
+            sysfs: sysfs.clone(),
+            dir,
+            remote,
+            settle_count: AtomicUsize::new(0),
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// `/sys/class/remoteproc/{id}` of the simulated remote processor
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// put a firmware in `/lib/firmware`, where start looks for it
+    pub fn install_firmware(&self, firmware_name: &str, image: &[u8]) -> io::Result<()> {
+        fs::write(self.sysfs.firmware_dir().join(firmware_name), image)
+    }
+
+    /// the failure the next start runs into
+    pub fn set_fault(&self, fault: RemoteprocFault) {
+        self.remote.lock().unwrap().fault = fault;
+    }
+
+    /// crash the firmware now, if it runs
+    pub fn crash(&self) -> io::Result<()> {
+        let mut remote = self.remote.lock().unwrap();
+        if matches!(
+            remote.state,
+            RemoteprocState::Running | RemoteprocState::Attached
+        ) {
+            remote.state = RemoteprocState::Crashed;
+            remote.crash_at = None;
+            write_attribute(&self.dir, "state", remote.state.as_str())?;
+        }
+        Ok(())
+    }
+
+    pub fn state(&self) -> RemoteprocState {
+        self.remote.lock().unwrap().state
+    }
+
+    pub fn firmware(&self) -> String {
+        self.remote.lock().unwrap().firmware.clone()
+    }
+
+    /// the error the kernel would have returned to the last refused write
+    pub fn take_error(&self) -> Option<Errno> {
+        self.remote.lock().unwrap().last_error.take()
+    }
+
+    /// wait until the simulator handled every write done so far
+    pub fn settle(&self) -> io::Result<()> {
+        let count = self.settle_count.fetch_add(1, Ordering::SeqCst);
+        let marker = self.dir.join(format!(".settle-{}", count));
+        fs::write(&marker, "")?;
+        // why: the events are handled in order, so the marker is removed after earlier writes
+        let deadline = Instant::now() + SETTLE_TIMEOUT;
+        while marker.exists() {
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "the remoteproc simulator didn't catch up",
+                ));
+            }
+            sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for RemoteprocSimulator {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// the part of the simulator which runs on its thread
+struct Simulation {
+    sysfs: SysfsRoot,
+    dir: PathBuf,
+    remote: Arc<Mutex<Remote>>,
+}
+
+impl Simulation {
+    fn run(&self, inotify: Inotify, stop: &AtomicBool) {
+        while !stop.load(Ordering::SeqCst) {
+            let mut fds = [PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, SIMULATOR_POLL_INTERVAL.as_millis() as i32) {
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(e) => {
+                    warn!("remoteproc simulator stopped: {}", e);
+                    return;
+                }
+            }
+            let events = inotify.read_events().unwrap_or_default();
+            for event in events {
+                let name = match event.name.as_ref().and_then(|name| name.to_str()) {
+                    Some(name) => name.to_string(),
+                    None => continue,
+                };
+                let result = match name.as_str() {
+                    "state" => self.store_state(),
+                    "firmware" => self.store_firmware(),
+                    marker if marker.starts_with(".settle-") => {
+                        fs::remove_file(self.dir.join(marker))
+                    }
+                    _ => Ok(()),
+                };
+                if let Err(e) = result {
+                    warn!("remoteproc simulator failed to handle {}: {}", name, e);
+                }
+            }
+            if let Err(e) = self.crash_when_due() {
+                warn!("remoteproc simulator failed to crash: {}", e);
+            }
+        }
+    }
+
+    fn store_state(&self) -> io::Result<()> {
+        let command = read_attribute(&self.dir, "state")?;
+        let mut remote = self.remote.lock().unwrap();
+        trace!("simulated remoteproc in {} got {}", remote.state, command);
+        let result = match command.as_str() {
+            "start" => self.start(&mut remote),
+            "stop" if remote.state.can_stop() => {
+                remote.state = RemoteprocState::Offline;
+                remote.crash_at = None;
+                Ok(())
+            }
+            _ => Err(Errno::EINVAL),
+        };
+        if let Err(e) = result {
+            remote.last_error = Some(e);
+        }
+        // why: the command is still in the file, put the state back in its place
+        write_attribute(&self.dir, "state", remote.state.as_str())
+    }
+
+    fn start(&self, remote: &mut Remote) -> Result<(), Errno> {
+        match remote.state {
+            RemoteprocState::Running | RemoteprocState::Attached => return Err(Errno::EBUSY),
+            RemoteprocState::Detached => {
+                remote.state = RemoteprocState::Attached;
+                return Ok(());
+            }
+            RemoteprocState::Offline => {}
+            _ => return Err(Errno::EINVAL),
+        }
+        let firmware_path = self.sysfs.firmware_dir().join(&remote.firmware);
+        if remote.fault == RemoteprocFault::MissingFirmware || !firmware_path.is_file() {
+            return Err(Errno::ENOENT);
+        }
+        remote.state = RemoteprocState::Running;
+        if let RemoteprocFault::CrashAfterStart(delay) = remote.fault {
+            remote.crash_at = Some(Instant::now() + delay);
+        }
+        Ok(())
+    }
+
+    fn store_firmware(&self) -> io::Result<()> {
+        let firmware = read_attribute(&self.dir, "firmware")?;
+        let mut remote = self.remote.lock().unwrap();
+        if remote.state != RemoteprocState::Offline {
+            remote.last_error = Some(Errno::EBUSY);
+        } else if firmware.is_empty() {
+            remote.last_error = Some(Errno::EINVAL);
+        } else {
+            remote.firmware = firmware;
+        }
+        write_attribute(&self.dir, "firmware", &remote.firmware)
+    }
+
+    fn crash_when_due(&self) -> io::Result<()> {
+        let mut remote = self.remote.lock().unwrap();
+        match remote.crash_at {
+            Some(crash_at) if Instant::now() >= crash_at => {
+                remote.state = RemoteprocState::Crashed;
+                remote.crash_at = None;
+                write_attribute(&self.dir, "state", remote.state.as_str())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn read_attribute(dir: &Path, name: &str) -> io::Result<String> {
+    Ok(fs::read_to_string(dir.join(name))?.trim().to_string())
+}
+
+// replace the attribute at once, so a reader never sees a half written value
+// and the simulator doesn't see its own writes, they close a file of another name
+fn write_attribute(dir: &Path, name: &str, value: &str) -> io::Result<()> {
+    let staging = dir.join(format!(".{}", name));
+    fs::write(&staging, format!("{}\n", value))?;
+    fs::rename(staging, dir.join(name))
+}
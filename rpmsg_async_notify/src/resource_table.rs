@@ -0,0 +1,476 @@
+use crate::firmware::{ElfError, FirmwareImage, RESOURCE_TABLE_SECTION};
+use snafu::{ResultExt, Snafu};
+use std::fmt;
+
+/// the only version of the resource table the kernel knows
+pub const RESOURCE_TABLE_VERSION: u32 = 1;
+/// an address the kernel picks, in a carveout or a vring
+pub const FW_RSC_ADDR_ANY: u32 = 0xffff_ffff;
+/// the virtio device id of rpmsg
+pub const VIRTIO_ID_RPMSG: u32 = 7;
+/// the feature bit of the rpmsg name service, the remote announces its channels with it
+pub const VIRTIO_RPMSG_F_NS: u32 = 0;
+
+const RSC_CARVEOUT: u32 = 0;
+const RSC_DEVMEM: u32 = 1;
+const RSC_TRACE: u32 = 2;
+const RSC_VDEV: u32 = 3;
+const TABLE_HEADER_LEN: usize = 16;
+const RESOURCE_NAME_LEN: usize = 32;
+const VDEV_HEADER_LEN: usize = 24;
+const VRING_LEN: usize = 20;
+
+#[derive(Debug, Snafu, Clone, PartialEq)]
+#[snafu(visibility = "pub")]
+pub enum ResourceTableError {
+    #[snafu(display("can't find the resource table, {}", source))]
+    NoResourceTable { source: ElfError },
+    #[snafu(display("unsupported resource table version {}", version))]
+    UnsupportedVersion { version: u32 },
+    #[snafu(display("truncated resource table, {} is out of the table", what))]
+    TruncatedTable { what: String },
+}
+
+/// memory the kernel allocates for the firmware
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Carveout {
+    /// the address the firmware sees, FW_RSC_ADDR_ANY when the kernel picks it
+    pub da: u32,
+    /// the physical address, FW_RSC_ADDR_ANY when the kernel picks it
+    pub pa: u32,
+    pub len: u32,
+    pub flags: u32,
+    pub name: String,
+}
+
+/// a region of device memory mapped for the firmware, e.g. peripheral registers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Devmem {
+    pub da: u32,
+    pub pa: u32,
+    pub len: u32,
+    pub flags: u32,
+    pub name: String,
+}
+
+/// a buffer the firmware logs to, shown in debugfs by the kernel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    pub da: u32,
+    pub len: u32,
+    pub name: String,
+}
+
+/// a vring of a vdev
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vring {
+    pub da: u32,
+    pub align: u32,
+    /// the number of buffers of the vring
+    pub num: u32,
+    pub notify_id: u32,
+    pub pa: u32,
+}
+
+/// a virtio device the firmware exposes, the rpmsg bus is one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vdev {
+    /// the virtio device id, VIRTIO_ID_RPMSG for rpmsg
+    pub id: u32,
+    pub notify_id: u32,
+    /// the features the firmware supports
+    pub dfeatures: u32,
+    /// the features the kernel acknowledged, written back at start
+    pub gfeatures: u32,
+    pub config_len: u32,
+    pub status: u8,
+    pub vrings: Vec<Vring>,
+}
+
+impl Vdev {
+    pub fn is_rpmsg(&self) -> bool {
+        self.id == VIRTIO_ID_RPMSG
+    }
+
+    pub fn has_feature(&self, bit: u32) -> bool {
+        bit < 32 && self.dfeatures & (1 << bit) != 0
+    }
+}
+
+/// an entry of the resource table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resource {
+    Carveout(Carveout),
+    Devmem(Devmem),
+    Trace(Trace),
+    Vdev(Vdev),
+    /// a vendor resource or one this parser doesn't know, the kernel skips the latter
+    Other {
+        kind: u32,
+    },
+}
+
+/// something in the resource table which keeps OctRPMsgChannel from working
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpmsgWarning {
+    /// without a vdev the kernel doesn't create the rpmsg bus
+    NoRpmsgVdev,
+    /// the vdev doesn't announce the name service, so the channels never show up
+    NoNameService { index: usize },
+    /// rpmsg needs a vring for each direction
+    MissingVrings { index: usize, count: usize },
+}
+
+impl fmt::Display for RpmsgWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpmsgWarning::NoRpmsgVdev => write!(f, "no rpmsg vdev, the rpmsg bus won't be created"),
+            RpmsgWarning::NoNameService { index } => write!(
+                f,
+                "resource {} misses VIRTIO_RPMSG_F_NS, the remote can't announce its channels",
+                index
+            ),
+            RpmsgWarning::MissingVrings { index, count } => {
+                write!(f, "resource {} has {} vrings, rpmsg needs 2", index, count)
+            }
+        }
+    }
+}
+
+/// # Resource table
+/// The resources a firmware asks remoteproc for, read from its `.resource_table` section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceTable {
+    pub version: u32,
+    pub resources: Vec<Resource>,
+}
+
+impl ResourceTable {
+    /// parse the table in the resource table section of firmware
+    pub fn from_firmware(firmware: &FirmwareImage) -> Result<Self, ResourceTableError> {
+        let table = firmware
+            .section_data(RESOURCE_TABLE_SECTION)
+            .context(NoResourceTable {})?;
+        ResourceTable::parse(table)
+    }
+
+    /// parse the table in data, the content of the resource table section
+    pub fn parse(data: &[u8]) -> Result<Self, ResourceTableError> {
+        let version = read_u32(data, 0, "the header")?;
+        if version != RESOURCE_TABLE_VERSION {
+            return Err(ResourceTableError::UnsupportedVersion { version });
+        }
+        let count = read_u32(data, 4, "the header")? as usize;
+        let mut resources = Vec::new();
+        for index in 0..count {
+            let what = format!("resource {}", index);
+            let offset = read_u32(data, TABLE_HEADER_LEN + index * 4, &what)? as usize;
+            resources.push(parse_resource(data, offset, &what)?);
+        }
+        Ok(ResourceTable { version, resources })
+    }
+
+    pub fn vdevs(&self) -> impl Iterator<Item = &Vdev> {
+        self.resources.iter().filter_map(|resource| match resource {
+            Resource::Vdev(vdev) => Some(vdev),
+            _ => None,
+        })
+    }
+
+    /// what keeps the rpmsg bus of this firmware from working with OctRPMsgChannel
+    pub fn rpmsg_warnings(&self) -> Vec<RpmsgWarning> {
+        let mut warnings = Vec::new();
+        let mut found = false;
+        for (index, resource) in self.resources.iter().enumerate() {
+            let vdev = match resource {
+                Resource::Vdev(vdev) if vdev.is_rpmsg() => vdev,
+                _ => continue,
+            };
+            found = true;
+            if !vdev.has_feature(VIRTIO_RPMSG_F_NS) {
+                warnings.push(RpmsgWarning::NoNameService { index });
+            }
+            if vdev.vrings.len() < 2 {
+                warnings.push(RpmsgWarning::MissingVrings {
+                    index,
+                    count: vdev.vrings.len(),
+                });
+            }
+        }
+        if !found {
+            warnings.push(RpmsgWarning::NoRpmsgVdev);
+        }
+        warnings
+    }
+}
+
+impl fmt::Display for ResourceTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "resource table version {}, {} entries",
+            self.version,
+            self.resources.len()
+        )?;
+        for (index, resource) in self.resources.iter().enumerate() {
+            match resource {
+                Resource::Carveout(carveout) => writeln!(
+                    f,
+                    "[{}] carveout {:<16} da {} pa {} len {:#x} flags {:#x}",
+                    index,
+                    carveout.name,
+                    address(carveout.da),
+                    address(carveout.pa),
+                    carveout.len,
+                    carveout.flags
+                )?,
+                Resource::Devmem(devmem) => writeln!(
+                    f,
+                    "[{}] devmem   {:<16} da {} pa {} len {:#x} flags {:#x}",
+                    index,
+                    devmem.name,
+                    address(devmem.da),
+                    address(devmem.pa),
+                    devmem.len,
+                    devmem.flags
+                )?,
+                Resource::Trace(trace) => writeln!(
+                    f,
+                    "[{}] trace    {:<16} da {} len {:#x}",
+                    index,
+                    trace.name,
+                    address(trace.da),
+                    trace.len
+                )?,
+                Resource::Vdev(vdev) => {
+                    writeln!(
+                        f,
+                        "[{}] vdev     id {}{} notifyid {} dfeatures {:#x} gfeatures {:#x} config {} bytes",
+                        index,
+                        vdev.id,
+                        if vdev.is_rpmsg() { " (rpmsg)" } else { "" },
+                        vdev.notify_id,
+                        vdev.dfeatures,
+                        vdev.gfeatures,
+                        vdev.config_len
+                    )?;
+                    for (vring_index, vring) in vdev.vrings.iter().enumerate() {
+                        writeln!(
+                            f,
+                            "      vring{} da {} pa {} num {} align {:#x} notifyid {}",
+                            vring_index,
+                            address(vring.da),
+                            address(vring.pa),
+                            vring.num,
+                            vring.align,
+                            vring.notify_id
+                        )?;
+                    }
+                }
+                Resource::Other { kind } => writeln!(f, "[{}] type {}", index, kind)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// an address of the table, or any when the kernel picks it
+fn address(addr: u32) -> String {
+    if addr == FW_RSC_ADDR_ANY {
+        "any".to_string()
+    } else {
+        format!("{:#010x}", addr)
+    }
+}
+
+fn parse_resource(data: &[u8], offset: usize, what: &str) -> Result<Resource, ResourceTableError> {
+    let kind = read_u32(data, offset, what)?;
+    // the fields follow the type
+    let body = offset + 4;
+    let field = |index: usize| read_u32(data, body + index * 4, what);
+    let resource = match kind {
+        RSC_CARVEOUT => Resource::Carveout(Carveout {
+            da: field(0)?,
+            pa: field(1)?,
+            len: field(2)?,
+            flags: field(3)?,
+            name: read_name(data, body + 20, what)?,
+        }),
+        RSC_DEVMEM => Resource::Devmem(Devmem {
+            da: field(0)?,
+            pa: field(1)?,
+            len: field(2)?,
+            flags: field(3)?,
+            name: read_name(data, body + 20, what)?,
+        }),
+        RSC_TRACE => Resource::Trace(Trace {
+            da: field(0)?,
+            len: field(1)?,
+            name: read_name(data, body + 12, what)?,
+        }),
+        RSC_VDEV => {
+            let header = bytes(data, body, VDEV_HEADER_LEN, what)?;
+            let vring_count = header[21] as usize;
+            let mut vrings = Vec::with_capacity(vring_count);
+            for vring_index in 0..vring_count {
+                let vring = body + VDEV_HEADER_LEN + vring_index * VRING_LEN;
+                let vring_field = |index: usize| read_u32(data, vring + index * 4, what);
+                vrings.push(Vring {
+                    da: vring_field(0)?,
+                    align: vring_field(1)?,
+                    num: vring_field(2)?,
+                    notify_id: vring_field(3)?,
+                    pa: vring_field(4)?,
+                });
+            }
+            Resource::Vdev(Vdev {
+                id: field(0)?,
+                notify_id: field(1)?,
+                dfeatures: field(2)?,
+                gfeatures: field(3)?,
+                config_len: field(4)?,
+                status: header[20],
+                vrings,
+            })
+        }
+        kind => Resource::Other { kind },
+    };
+    Ok(resource)
+}
+
+fn bytes<'a>(
+    data: &'a [u8],
+    offset: usize,
+    len: usize,
+    what: &str,
+) -> Result<&'a [u8], ResourceTableError> {
+    data.get(offset..offset + len)
+        .ok_or_else(|| ResourceTableError::TruncatedTable {
+            what: what.to_string(),
+        })
+}
+
+fn read_u32(data: &[u8], offset: usize, what: &str) -> Result<u32, ResourceTableError> {
+    let bytes = bytes(data, offset, 4, what)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// a name padded with zeros to RESOURCE_NAME_LEN
+fn read_name(data: &[u8], offset: usize, what: &str) -> Result<String, ResourceTableError> {
+    let name = bytes(data, offset, RESOURCE_NAME_LEN, what)?;
+    let len = name
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(name.len());
+    Ok(String::from_utf8_lossy(&name[..len]).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::firmware::tests::fake_firmware;
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize(RESOURCE_NAME_LEN, 0);
+        bytes
+    }
+
+    // a table like the one of the OpenAMP echo demo on the R5
+    fn echo_table(dfeatures: u32) -> Vec<u8> {
+        let mut carveout = words(&[RSC_CARVEOUT, 0x3ed0_0000, 0x3ed0_0000, 0x40000, 0, 0]);
+        carveout.extend(name("r5_0_ddr"));
+        let mut trace = words(&[RSC_TRACE, 0x3ed4_0000, 0x2000, 0]);
+        trace.extend(name("r5_trace"));
+        let mut vdev = words(&[RSC_VDEV, VIRTIO_ID_RPMSG, 0, dfeatures, 0, 0]);
+        vdev.extend([0, 2, 0, 0]);
+        vdev.extend(words(&[FW_RSC_ADDR_ANY, 0x1000, 256, 1, 0]));
+        vdev.extend(words(&[FW_RSC_ADDR_ANY, 0x1000, 256, 2, 0]));
+        let vendor = words(&[128, 0]);
+
+        let entries = [carveout, trace, vdev, vendor];
+        let mut offset = TABLE_HEADER_LEN + entries.len() * 4;
+        let mut offsets = Vec::new();
+        for entry in &entries {
+            offsets.push(offset as u32);
+            offset += entry.len();
+        }
+        let mut table = words(&[RESOURCE_TABLE_VERSION, entries.len() as u32, 0, 0]);
+        table.extend(words(&offsets));
+        for entry in entries {
+            table.extend(entry);
+        }
+        table
+    }
+
+    #[test]
+    fn parse_echo_table() {
+        let elf = fake_firmware(&[
+            (".text", &[0xfe; 16]),
+            (RESOURCE_TABLE_SECTION, &echo_table(1 << VIRTIO_RPMSG_F_NS)),
+        ]);
+        let table = ResourceTable::from_firmware(&FirmwareImage::parse(&elf).unwrap()).unwrap();
+        assert_eq!(table.resources.len(), 4);
+        assert_eq!(
+            table.resources[0],
+            Resource::Carveout(Carveout {
+                da: 0x3ed0_0000,
+                pa: 0x3ed0_0000,
+                len: 0x40000,
+                flags: 0,
+                name: "r5_0_ddr".to_string(),
+            })
+        );
+        assert_eq!(
+            table.resources[1],
+            Resource::Trace(Trace {
+                da: 0x3ed4_0000,
+                len: 0x2000,
+                name: "r5_trace".to_string(),
+            })
+        );
+        let vdev = table.vdevs().next().unwrap();
+        assert!(vdev.is_rpmsg());
+        assert_eq!(vdev.vrings.len(), 2);
+        assert_eq!(vdev.vrings[1].notify_id, 2);
+        assert_eq!(vdev.vrings[1].da, FW_RSC_ADDR_ANY);
+        assert_eq!(table.resources[3], Resource::Other { kind: 128 });
+        assert_eq!(table.rpmsg_warnings(), Vec::new());
+        let printed = table.to_string();
+        assert!(printed.contains("carveout r5_0_ddr"));
+        assert!(printed.contains("vring1 da any"));
+    }
+
+    #[test]
+    fn warn_about_rpmsg() {
+        let table = ResourceTable::parse(&echo_table(0)).unwrap();
+        assert_eq!(
+            table.rpmsg_warnings(),
+            vec![RpmsgWarning::NoNameService { index: 2 }]
+        );
+        let empty = ResourceTable::parse(&words(&[1, 0, 0, 0])).unwrap();
+        assert_eq!(empty.rpmsg_warnings(), vec![RpmsgWarning::NoRpmsgVdev]);
+
+        assert_eq!(
+            ResourceTable::parse(&words(&[2, 0, 0, 0])),
+            Err(ResourceTableError::UnsupportedVersion { version: 2 })
+        );
+        let table = echo_table(1);
+        assert!(matches!(
+            ResourceTable::parse(&table[..table.len() - 12]),
+            Err(ResourceTableError::TruncatedTable { what }) if what == "resource 2"
+        ));
+        let elf = fake_firmware(&[(".text", &[0xfe; 16])]);
+        assert!(matches!(
+            ResourceTable::from_firmware(&FirmwareImage::parse(&elf).unwrap()),
+            Err(ResourceTableError::NoResourceTable { .. })
+        ));
+    }
+}
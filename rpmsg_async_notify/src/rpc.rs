@@ -0,0 +1,376 @@
+use crate::channel::{AbstractRPMsgChannel, ChannelError};
+use crate::MAX_RPMSG_BUFF_SIZE;
+use bincode::{deserialize, serialize_into, serialized_size};
+use log::{trace, warn};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use std::os::unix::prelude::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// size of the header in front of every rpc frame: seq(u32, little endian) + kind(u8)
+pub const RPC_HEADER_LEN: usize = 5;
+
+/// what a frame carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameKind {
+    /// host to remote, expects a response with the same seq
+    Request = 0,
+    /// remote to host, answers the request with the same seq
+    Response = 1,
+    /// remote to host, not related to any request, seq is ignored
+    Notification = 2,
+}
+
+/// one message of the rpc protocol, the body is encoded with bincode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcFrame {
+    pub seq: u32,
+    pub kind: FrameKind,
+    pub body: Vec<u8>,
+}
+
+impl RpcFrame {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(RPC_HEADER_LEN + self.body.len());
+        buf.extend_from_slice(&self.seq.to_le_bytes());
+        buf.push(self.kind as u8);
+        buf.extend_from_slice(&self.body);
+        buf
+    }
+
+    pub fn decode(raw: Vec<u8>) -> Result<RpcFrame, ChannelError> {
+        let kind = match raw.get(4) {
+            Some(0) => FrameKind::Request,
+            Some(1) => FrameKind::Response,
+            Some(2) => FrameKind::Notification,
+            _ => {
+                return Err(ChannelError::FailedToDecode {
+                    error: "not an rpc frame".to_string(),
+                    raw,
+                })
+            }
+        };
+        let seq = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+        Ok(RpcFrame {
+            seq,
+            kind,
+            body: raw[RPC_HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+/// handler for the messages the remote sends on its own
+pub type UnsolicitedHandler = Box<dyn FnMut(RpcFrame) + Send>;
+
+/// # Rpc client
+/// Send requests over a channel and wait for the matching responses.
+/// Every request gets a sequence id, responses are matched by that id, so they can come back
+/// in any order. Notifications from the remote go to the unsolicited handler.
+/// The channel has to be non-blocking, the client waits on its descriptor with poll(2).
+pub struct RpcClient<Req, Resp, C>
+where
+    C: AbstractRPMsgChannel + AsRawFd,
+{
+    channel: C,
+    next_seq: u32,
+    // requests sent and not answered yet
+    outstanding: HashSet<u32>,
+    // responses received while waiting for another request
+    responses: HashMap<u32, Vec<u8>>,
+    unsolicited: Option<UnsolicitedHandler>,
+    // why: the client doesn't store Req or Resp, it only encodes and decodes them
+    _message: PhantomData<fn(Req) -> Resp>,
+}
+
+impl<Req, Resp, C> RpcClient<Req, Resp, C>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned,
+    C: AbstractRPMsgChannel + AsRawFd,
+{
+    pub fn new(channel: C) -> Self {
+        RpcClient {
+            channel,
+            next_seq: 1,
+            outstanding: HashSet::new(),
+            responses: HashMap::new(),
+            unsolicited: None,
+            _message: PhantomData,
+        }
+    }
+
+    /// handle notifications from the remote, without a handler they are dropped
+    pub fn on_unsolicited<F>(&mut self, handler: F)
+    where
+        F: FnMut(RpcFrame) + Send + 'static,
+    {
+        self.unsolicited = Some(Box::new(handler));
+    }
+
+    /// send request and wait up to timeout for its response
+    pub fn call(&mut self, request: &Req, timeout: Duration) -> Result<Resp, ChannelError> {
+        let seq = self.send_request(request)?;
+        self.wait_response(seq, timeout)
+    }
+
+    /// send request without waiting, the returned seq is used to pick up the response
+    pub fn send_request(&mut self, request: &Req) -> Result<u32, ChannelError> {
+        let body_size = serialized_size(request).map_err(|e| ChannelError::FailedToSerialize {
+            error: format!("{:?}", e),
+        })? as usize;
+        let max_size = self.channel.max_message_size();
+        if RPC_HEADER_LEN + body_size > max_size {
+            return Err(ChannelError::MessageTooLarge {
+                message_size: RPC_HEADER_LEN + body_size,
+                max_size,
+            });
+        }
+        let mut body = Vec::with_capacity(body_size);
+        serialize_into(&mut body, request).map_err(|e| ChannelError::FailedToSerialize {
+            error: format!("{:?}", e),
+        })?;
+
+        let seq = self.allocate_seq();
+        let frame = RpcFrame {
+            seq,
+            kind: FrameKind::Request,
+            body,
+        };
+        self.channel.send(&frame.encode())?;
+        self.outstanding.insert(seq);
+        trace!("sent request {}", seq);
+        Ok(seq)
+    }
+
+    /// wait up to timeout for the response of the request identified by seq
+    /// the request is forgotten on timeout, a late response is dropped
+    /// SysError EPIPE is returned if the remote hangs up before answering
+    pub fn wait_response(&mut self, seq: u32, timeout: Duration) -> Result<Resp, ChannelError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(body) = self.responses.remove(&seq) {
+                return deserialize(&body).map_err(|e| ChannelError::FailedToDecode {
+                    error: format!("{:?}", e),
+                    raw: body,
+                });
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.wait_readable(remaining)? {
+                self.outstanding.remove(&seq);
+                return Err(ChannelError::RpcTimeout { seq, timeout });
+            }
+            if let Err(e) = self.poll_incoming() {
+                self.outstanding.remove(&seq);
+                return Err(e);
+            }
+        }
+    }
+
+    /// read every pending message without blocking and route it
+    /// return SysError EPIPE once the remote hung up
+    pub fn poll_incoming(&mut self) -> Result<(), ChannelError> {
+        loop {
+            let raw = match self.channel.read(MAX_RPMSG_BUFF_SIZE as usize) {
+                // why: an empty read is the end of file, the remote won't send anything else
+                Ok(raw) if raw.is_empty() => {
+                    return Err(ChannelError::SysError {
+                        source: Errno::EPIPE,
+                    })
+                }
+                Ok(raw) => raw,
+                Err(ChannelError::WouldBlock { .. }) => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let frame = match RpcFrame::decode(raw) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    warn!("dropping message: {}", e);
+                    continue;
+                }
+            };
+            self.route(frame);
+        }
+    }
+
+    /// number of requests waiting for a response
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    pub fn get_ref(&self) -> &C {
+        &self.channel
+    }
+
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.channel
+    }
+
+    pub fn into_inner(self) -> C {
+        self.channel
+    }
+
+    fn route(&mut self, frame: RpcFrame) {
+        match frame.kind {
+            FrameKind::Response if self.outstanding.remove(&frame.seq) => {
+                trace!("received response {}", frame.seq);
+                self.responses.insert(frame.seq, frame.body);
+            }
+            FrameKind::Response => warn!("dropping response to unknown request {}", frame.seq),
+            FrameKind::Notification => match self.unsolicited.as_mut() {
+                Some(handler) => handler(frame),
+                None => warn!("dropping notification, no handler registered"),
+            },
+            FrameKind::Request => warn!("dropping request {} sent to the host", frame.seq),
+        }
+    }
+
+    // skip 0 and sequence ids still in use after wrapping around
+    fn allocate_seq(&mut self) -> u32 {
+        loop {
+            let seq = self.next_seq;
+            self.next_seq = self.next_seq.wrapping_add(1);
+            if seq != 0 && !self.outstanding.contains(&seq) && !self.responses.contains_key(&seq) {
+                return seq;
+            }
+        }
+    }
+
+    // true if the channel became readable before timeout
+    fn wait_readable(&self, timeout: Duration) -> Result<bool, ChannelError> {
+        let mut fds = [PollFd::new(self.channel.as_raw_fd(), PollFlags::POLLIN)];
+        let timeout_ms = timeout.as_millis().clamp(1, i32::MAX as u128) as i32;
+        match poll(&mut fds, timeout_ms) {
+            Ok(ready) => Ok(ready > 0),
+            Err(Errno::EINTR) => Ok(true),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::CHECKSUM_LEN;
+    use crate::loopback::LoopbackRPMsgChannel;
+    use std::sync::mpsc::channel;
+
+    fn response(seq: u32, value: u32) -> Vec<u8> {
+        RpcFrame {
+            seq,
+            kind: FrameKind::Response,
+            body: bincode::serialize(&value).unwrap(),
+        }
+        .encode()
+    }
+
+    fn read_request(remote: &mut LoopbackRPMsgChannel) -> (u32, u32) {
+        let frame = RpcFrame::decode(remote.read(MAX_RPMSG_BUFF_SIZE as usize).unwrap()).unwrap();
+        assert_eq!(frame.kind, FrameKind::Request);
+        (frame.seq, bincode::deserialize(&frame.body).unwrap())
+    }
+
+    #[test]
+    fn call_returns_matching_response() {
+        let (host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut client = RpcClient::<u32, u32, _>::new(host);
+        let first = client.send_request(&10).unwrap();
+        let second = client.send_request(&20).unwrap();
+        assert_ne!(first, second);
+        let (seq_a, value_a) = read_request(&mut remote);
+        let (seq_b, value_b) = read_request(&mut remote);
+        // answer out of order
+        remote.send(&response(seq_b, value_b + 1)).unwrap();
+        remote.send(&response(seq_a, value_a + 1)).unwrap();
+
+        assert_eq!(
+            client.wait_response(first, Duration::from_millis(100)),
+            Ok(11)
+        );
+        assert_eq!(
+            client.wait_response(second, Duration::from_millis(100)),
+            Ok(21)
+        );
+        assert_eq!(client.outstanding(), 0);
+    }
+
+    #[test]
+    fn missing_response_times_out() {
+        let (host, _remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut client = RpcClient::<u32, u32, _>::new(host);
+        let timeout = Duration::from_millis(5);
+        assert_eq!(
+            client.call(&1, timeout),
+            Err(ChannelError::RpcTimeout { seq: 1, timeout })
+        );
+        assert_eq!(client.outstanding(), 0);
+    }
+
+    #[test]
+    fn hang_up_ends_the_call() {
+        let (host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut client = RpcClient::<u32, u32, _>::new(host);
+        drop(remote);
+        let start = Instant::now();
+        assert_eq!(
+            client.call(&1, Duration::from_secs(5)),
+            Err(ChannelError::SysError {
+                source: Errno::EPIPE
+            })
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(client.outstanding(), 0);
+    }
+
+    #[test]
+    fn request_fits_the_channel() {
+        let (mut host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        host.set_checksum(true);
+        let max_size = host.max_message_size();
+        let mut client = RpcClient::<Vec<u8>, u32, _>::new(host);
+        // bincode puts the length of the vector in 8 bytes in front of it
+        let largest = vec![0u8; max_size - RPC_HEADER_LEN - 8];
+        client.send_request(&largest).unwrap();
+        assert_eq!(
+            remote.read(MAX_RPMSG_BUFF_SIZE as usize).unwrap().len(),
+            max_size + CHECKSUM_LEN
+        );
+        assert_eq!(
+            client.send_request(&vec![0u8; largest.len() + 1]),
+            Err(ChannelError::MessageTooLarge {
+                message_size: max_size + 1,
+                max_size,
+            })
+        );
+    }
+
+    #[test]
+    fn notifications_go_to_handler() {
+        let (host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut client = RpcClient::<u32, u32, _>::new(host);
+        let (tx, rx) = channel();
+        client.on_unsolicited(move |frame| tx.send(frame.body).unwrap());
+
+        let seq = client.send_request(&5).unwrap();
+        remote
+            .send(
+                &RpcFrame {
+                    seq: 0,
+                    kind: FrameKind::Notification,
+                    body: vec![9, 9],
+                }
+                .encode(),
+            )
+            .unwrap();
+        // a late response of a forgotten request is dropped
+        remote.send(&response(seq + 100, 0)).unwrap();
+        remote.send(&response(seq, 6)).unwrap();
+
+        assert_eq!(client.wait_response(seq, Duration::from_millis(100)), Ok(6));
+        assert_eq!(rx.try_recv(), Ok(vec![9, 9]));
+    }
+}
@@ -0,0 +1,153 @@
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// # Endpoint counters
+/// Traffic and error counters kept by every endpoint.
+/// Each update is a single relaxed atomic add, so they stay enabled in production.
+/// The counters are independent, a snapshot taken during traffic may be off by a message.
+#[derive(Debug, Default)]
+pub(crate) struct EndpointCounters {
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_received: AtomicU64,
+    short_writes: AtomicU64,
+    overflows: AtomicU64,
+    would_block: AtomicU64,
+    checksum_mismatches: AtomicU64,
+}
+
+impl EndpointCounters {
+    pub(crate) fn sent(&self, size: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn received(&self, size: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn short_write(&self) {
+        self.short_writes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn overflow(&self) {
+        self.overflows.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn would_block(&self) {
+        self.would_block.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn checksum_mismatch(&self) {
+        self.checksum_mismatches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> EndpointStats {
+        EndpointStats {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            short_writes: self.short_writes.load(Ordering::Relaxed),
+            overflows: self.overflows.load(Ordering::Relaxed),
+            would_block: self.would_block.load(Ordering::Relaxed),
+            checksum_mismatches: self.checksum_mismatches.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        for counter in [
+            &self.messages_sent,
+            &self.bytes_sent,
+            &self.messages_received,
+            &self.bytes_received,
+            &self.short_writes,
+            &self.overflows,
+            &self.would_block,
+            &self.checksum_mismatches,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// a snapshot of the counters of an endpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct EndpointStats {
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_received: u64,
+    pub bytes_received: u64,
+    /// writes which didn't take the whole message, reported as FailedToSend
+    pub short_writes: u64,
+    /// messages larger than the buffer of the read, reported as MessageBufferOverflow
+    pub overflows: u64,
+    /// reads with nothing pending and sends with no room, reported as WouldBlock
+    pub would_block: u64,
+    /// messages dropped by the checksum framing, reported as ChecksumMismatch
+    pub checksum_mismatches: u64,
+}
+
+impl fmt::Display for EndpointStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sent {} messages ({} bytes), received {} messages ({} bytes), \
+             {} short writes, {} overflows, {} would block, {} checksum mismatches",
+            self.messages_sent,
+            self.bytes_sent,
+            self.messages_received,
+            self.bytes_received,
+            self.short_writes,
+            self.overflows,
+            self.would_block,
+            self.checksum_mismatches
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{AbstractRPMsgChannel, ChannelError};
+    use crate::loopback::LoopbackRPMsgChannel;
+    use crate::MAX_RPMSG_BUFF_SIZE;
+
+    #[test]
+    fn count_traffic_and_errors() {
+        let (mut host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        host.send(b"hello").unwrap();
+        host.send(&[0u8; 64]).unwrap();
+        assert_eq!(remote.read(MAX_RPMSG_BUFF_SIZE as usize).unwrap(), b"hello");
+        assert!(matches!(
+            remote.read(16),
+            Err(ChannelError::MessageBufferOverflow { .. })
+        ));
+        assert!(remote.read(MAX_RPMSG_BUFF_SIZE as usize).is_err());
+
+        assert_eq!(
+            host.stats(),
+            EndpointStats {
+                messages_sent: 2,
+                bytes_sent: 69,
+                ..EndpointStats::default()
+            }
+        );
+        assert_eq!(
+            remote.stats(),
+            EndpointStats {
+                messages_received: 2,
+                bytes_received: 22,
+                overflows: 1,
+                would_block: 1,
+                ..EndpointStats::default()
+            }
+        );
+        remote.reset_stats();
+        assert_eq!(remote.stats(), EndpointStats::default());
+    }
+}
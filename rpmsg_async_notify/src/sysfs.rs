@@ -0,0 +1,131 @@
+use std::path::{Path, PathBuf};
+
+/// the root of the file system the rpmsg discovery code looks at
+/// on a real board it is `/`, in tests it can point to a fake tree
+/// which mimics the layout of sysfs and devfs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SysfsRoot {
+    root: PathBuf,
+}
+
+impl Default for SysfsRoot {
+    fn default() -> Self {
+        SysfsRoot {
+            root: PathBuf::from("/"),
+        }
+    }
+}
+
+impl SysfsRoot {
+    /// use the directory specified by root as the root of sysfs and devfs
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        SysfsRoot { root: root.into() }
+    }
+
+    /// the root directory of this environment
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// true for the root of the running system, where the kernel sends uevents
+    pub fn is_system(&self) -> bool {
+        self.root == Path::new("/")
+    }
+
+    /// `/sys/bus/rpmsg/devices`, where the rpmsg devices announced by the remote show up
+    pub fn rpmsg_devices_dir(&self) -> PathBuf {
+        self.root.join("sys/bus/rpmsg/devices")
+    }
+
+    /// `/sys/bus/rpmsg/devices/{device_name}`
+    pub fn rpmsg_device_dir(&self, device_name: &str) -> PathBuf {
+        self.rpmsg_devices_dir().join(device_name)
+    }
+
+    /// `/sys/bus/rpmsg/drivers/{driver_name}`
+    pub fn rpmsg_driver_dir(&self, driver_name: &str) -> PathBuf {
+        self.root.join("sys/bus/rpmsg/drivers").join(driver_name)
+    }
+
+    /// `/sys/class/rpmsg`, where the control interfaces and their endpoints are registered
+    pub fn rpmsg_class_dir(&self) -> PathBuf {
+        self.root.join("sys/class/rpmsg")
+    }
+
+    /// `/sys/class/remoteproc`, where the remote processors are registered
+    pub fn remoteproc_class_dir(&self) -> PathBuf {
+        self.root.join("sys/class/remoteproc")
+    }
+
+    /// `/sys/class/remoteproc/{remoteproc_id}`
+    pub fn remoteproc_dir(&self, remoteproc_id: &str) -> PathBuf {
+        self.remoteproc_class_dir().join(remoteproc_id)
+    }
+
+    /// `/lib/firmware`, where the kernel looks for the firmware of a remote processor
+    pub fn firmware_dir(&self) -> PathBuf {
+        self.root.join("lib/firmware")
+    }
+
+    /// `/dev`
+    pub fn dev_dir(&self) -> PathBuf {
+        self.root.join("dev")
+    }
+
+    /// `/dev/{node_name}`
+    pub fn dev_node(&self, node_name: &str) -> PathBuf {
+        self.dev_dir().join(node_name)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::SysfsRoot;
+    use std::fs;
+    use std::path::Path;
+
+    /// build a fake tree which looks like a Zynq board after the RPU announced device_name
+    /// the control interface is rpmsg_ctrl0 and the endpoints are registered as rpmsg{i}
+    pub(crate) fn fake_rpmsg_tree(root: &Path, device_name: &str, driver_name: &str) -> SysfsRoot {
+        let sysfs = SysfsRoot::new(root);
+        let device_dir = sysfs.rpmsg_device_dir(device_name);
+        fs::create_dir_all(device_dir.join("rpmsg/rpmsg_ctrl0")).unwrap();
+        fs::write(device_dir.join("driver_override"), "").unwrap();
+        let driver_dir = sysfs.rpmsg_driver_dir(driver_name);
+        fs::create_dir_all(&driver_dir).unwrap();
+        fs::write(driver_dir.join("bind"), "").unwrap();
+        fs::create_dir_all(sysfs.rpmsg_class_dir().join("rpmsg_ctrl0")).unwrap();
+        fs::create_dir_all(sysfs.dev_dir()).unwrap();
+        fs::write(sysfs.dev_node("rpmsg_ctrl0"), "").unwrap();
+        sysfs
+    }
+
+    /// register an endpoint named endpoint_name as rpmsg{index} under rpmsg_ctrl0
+    pub(crate) fn fake_endpoint(sysfs: &SysfsRoot, index: usize, endpoint_name: &str) {
+        let endpoint_dir = sysfs
+            .rpmsg_class_dir()
+            .join("rpmsg_ctrl0")
+            .join(format!("rpmsg{}", index));
+        fs::create_dir_all(&endpoint_dir).unwrap();
+        fs::write(endpoint_dir.join("name"), format!("{}\n", endpoint_name)).unwrap();
+        fs::write(sysfs.dev_node(&format!("rpmsg{}", index)), "").unwrap();
+    }
+
+    #[test]
+    fn paths_are_relative_to_root() {
+        let sysfs = SysfsRoot::new("/tmp/board");
+        assert_eq!(
+            sysfs.rpmsg_device_dir("virtio0.demo.-1.0"),
+            Path::new("/tmp/board/sys/bus/rpmsg/devices/virtio0.demo.-1.0")
+        );
+        assert_eq!(sysfs.dev_node("rpmsg0"), Path::new("/tmp/board/dev/rpmsg0"));
+        assert_eq!(
+            SysfsRoot::default().rpmsg_class_dir(),
+            Path::new("/sys/class/rpmsg")
+        );
+        assert_eq!(
+            sysfs.remoteproc_dir("remoteproc0"),
+            Path::new("/tmp/board/sys/class/remoteproc/remoteproc0")
+        );
+    }
+}
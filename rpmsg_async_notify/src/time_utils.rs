@@ -1,5 +1,13 @@
+use nix::time::{clock_gettime, ClockId};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub fn unix_time(t: SystemTime) -> Duration {
     return t.duration_since(UNIX_EPOCH).expect("Time went backwards");
 }
+
+/// time on CLOCK_MONOTONIC, the clock of the kernel log, so both can be lined up
+pub fn monotonic_time() -> Duration {
+    clock_gettime(ClockId::CLOCK_MONOTONIC)
+        .map(Duration::from)
+        .expect("CLOCK_MONOTONIC is always available on linux")
+}
@@ -0,0 +1,135 @@
+use crate::channel::{AbstractRPMsgChannel, ChannelError};
+use crate::{MAX_RPMSG_BUFF_SIZE, PAYLOAD_MAX_SIZE};
+use bincode::{deserialize, serialize_into, serialized_size};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+/// # Typed channel
+/// Send Tx and receive Rx over a channel, messages are encoded with bincode.
+/// Outgoing messages larger than the limit are rejected before they reach the endpoint,
+/// the limit defaults to PAYLOAD_MAX_SIZE.
+pub struct TypedChannel<Tx, Rx, C: AbstractRPMsgChannel> {
+    channel: C,
+    max_message_size: usize,
+    // why: the channel doesn't store Tx or Rx, it only encodes and decodes them
+    _message: PhantomData<fn(Tx) -> Rx>,
+}
+
+impl<Tx, Rx, C> TypedChannel<Tx, Rx, C>
+where
+    Tx: Serialize,
+    Rx: DeserializeOwned,
+    C: AbstractRPMsgChannel,
+{
+    pub fn new(channel: C) -> Self {
+        TypedChannel {
+            channel,
+            max_message_size: PAYLOAD_MAX_SIZE,
+            _message: PhantomData,
+        }
+    }
+
+    /// change the largest encoded message send accepts
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    pub fn max_message_size(&self) -> usize {
+        self.max_message_size
+    }
+
+    /// encode the message and send it as one rpmsg message
+    pub fn send(&mut self, message: &Tx) -> Result<(), ChannelError> {
+        let message_size =
+            serialized_size(message).map_err(|e| ChannelError::FailedToSerialize {
+                error: format!("{:?}", e),
+            })? as usize;
+        if message_size > self.max_message_size {
+            return Err(ChannelError::MessageTooLarge {
+                message_size,
+                max_size: self.max_message_size,
+            });
+        }
+        let mut buf = Vec::with_capacity(message_size);
+        serialize_into(&mut buf, message).map_err(|e| ChannelError::FailedToSerialize {
+            error: format!("{:?}", e),
+        })?;
+        self.channel.send(&buf)
+    }
+
+    /// read one message and decode it
+    /// the errors of the channel are passed through, e.g. when no message is pending
+    pub fn recv(&mut self) -> Result<Rx, ChannelError> {
+        let raw = self.channel.read(MAX_RPMSG_BUFF_SIZE as usize)?;
+        deserialize(&raw).map_err(|e| ChannelError::FailedToDecode {
+            error: format!("{:?}", e),
+            raw,
+        })
+    }
+
+    pub fn get_ref(&self) -> &C {
+        &self.channel
+    }
+
+    pub fn get_mut(&mut self) -> &mut C {
+        &mut self.channel
+    }
+
+    pub fn into_inner(self) -> C {
+        self.channel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loopback::LoopbackRPMsgChannel;
+    use crate::Payload;
+
+    #[test]
+    fn payload_round_trip() {
+        let (host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut host = TypedChannel::<Payload, Payload, _>::new(host);
+        let mut remote = TypedChannel::<Payload, Payload, _>::new(remote);
+        host.send(&Payload::new(42)).unwrap();
+        let received = remote.recv().unwrap();
+        assert_eq!(received.num, 42);
+        assert_eq!(received.data, vec![0; 5]);
+    }
+
+    #[test]
+    fn oversize_message_is_not_sent() {
+        let (host, mut remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut host = TypedChannel::<Payload, Payload, _>::new(host);
+        let payload = Payload {
+            num: 1,
+            data: vec![0; PAYLOAD_MAX_SIZE],
+        };
+        assert_eq!(
+            host.send(&payload),
+            Err(ChannelError::MessageTooLarge {
+                message_size: PAYLOAD_MAX_SIZE + 16,
+                max_size: PAYLOAD_MAX_SIZE,
+            })
+        );
+        assert_eq!(
+            remote.read(MAX_RPMSG_BUFF_SIZE as usize),
+            Err(ChannelError::WouldBlock {
+                endpoint_name: "loopback:remote".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn decode_failure_keeps_raw_bytes() {
+        let (mut host, remote) = LoopbackRPMsgChannel::pair().unwrap();
+        let mut remote = TypedChannel::<Payload, Payload, _>::new(remote);
+        host.send(&[1, 2, 3]).unwrap();
+        match remote.recv() {
+            Err(ChannelError::FailedToDecode { raw, .. }) => assert_eq!(raw, vec![1, 2, 3]),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
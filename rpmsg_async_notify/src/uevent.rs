@@ -0,0 +1,141 @@
+use log::trace;
+use nix::errno::Errno;
+use nix::sys::socket::{
+    bind, recv, socket, AddressFamily, MsgFlags, SockAddr, SockFlag, SockProtocol, SockType,
+};
+use std::io;
+use std::os::unix::prelude::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+/// the multicast group the kernel sends its uevents to, udev resends them to group 2
+const KERNEL_UEVENT_GROUP: u32 = 1;
+/// a uevent is at most a page long
+const UEVENT_BUFFER_SIZE: usize = 4096;
+
+/// a kernel uevent, sent when a device is added, bound or removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uevent {
+    /// `add`, `bind`, `remove`, ...
+    pub action: String,
+    /// the path of the device under `/sys`, e.g. `/devices/.../virtio0.rpmsg-openamp-demo-channel.-1.0`
+    pub devpath: String,
+    /// `rpmsg` for the rpmsg devices
+    pub subsystem: String,
+}
+
+impl Uevent {
+    /// parse a message of the kernel, `{action}@{devpath}` followed by `KEY=value` lines,
+    /// all terminated by NUL
+    pub fn parse(message: &[u8]) -> Option<Uevent> {
+        let mut fields = message
+            .split(|byte| *byte == 0)
+            .filter(|field| !field.is_empty())
+            .map(String::from_utf8_lossy);
+        let header = fields.next()?;
+        // why: messages resent by udev start with libudev, they are not of the kernel
+        let (action, devpath) = header.split_once('@')?;
+        let mut uevent = Uevent {
+            action: action.to_string(),
+            devpath: devpath.to_string(),
+            subsystem: String::new(),
+        };
+        for field in fields {
+            if let Some(subsystem) = field.strip_prefix("SUBSYSTEM=") {
+                uevent.subsystem = subsystem.to_string();
+            }
+        }
+        Some(uevent)
+    }
+
+    /// the name of the device, the last component of devpath
+    pub fn device_name(&self) -> &str {
+        self.devpath.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// true if the uevent tells the rpmsg device called device_name appeared
+    pub fn announces_rpmsg_device(&self, device_name: &str) -> bool {
+        self.action == "add" && self.subsystem == "rpmsg" && self.device_name() == device_name
+    }
+}
+
+/// # Uevent socket
+/// A non-blocking NETLINK_KOBJECT_UEVENT socket which receives the uevents of the kernel.
+/// sysfs doesn't report new entries through inotify, the uevents are how to learn
+/// that a device was added without polling the file system.
+pub struct UeventSocket {
+    fd: OwnedFd,
+}
+
+impl UeventSocket {
+    pub fn open() -> io::Result<Self> {
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Datagram,
+            SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC,
+            SockProtocol::NetlinkKObjectUEvent,
+        )?;
+        // why: socket returned a new descriptor which nobody else owns
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        bind(
+            fd.as_raw_fd(),
+            &SockAddr::new_netlink(0, KERNEL_UEVENT_GROUP),
+        )?;
+        Ok(UeventSocket { fd })
+    }
+
+    /// the uevents received so far, without blocking
+    /// fails with ENOBUFS when the kernel dropped uevents because the socket was full
+    pub fn read_events(&self) -> io::Result<Vec<Uevent>> {
+        let mut events = Vec::new();
+        let mut buffer = [0u8; UEVENT_BUFFER_SIZE];
+        loop {
+            match recv(self.fd.as_raw_fd(), &mut buffer, MsgFlags::empty()) {
+                Ok(size) => {
+                    if let Some(event) = Uevent::parse(&buffer[..size]) {
+                        trace!("uevent {} {}", event.action, event.devpath);
+                        events.push(event);
+                    }
+                }
+                Err(Errno::EAGAIN) => return Ok(events),
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl AsRawFd for UeventSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_uevents() {
+        let devpath = "/devices/platform/r5f@0/remoteproc/remoteproc0/rproc-virtio.0.auto/virtio0/virtio0.rpmsg-openamp-demo-channel.-1.0";
+        let message = format!(
+            "add@{}\0ACTION=add\0DEVPATH={}\0SUBSYSTEM=rpmsg\0MODALIAS=rpmsg:rpmsg-openamp-demo-channel\0SEQNUM=2043\0",
+            devpath, devpath
+        );
+        let uevent = Uevent::parse(message.as_bytes()).unwrap();
+        assert_eq!(uevent.action, "add");
+        assert_eq!(uevent.devpath, devpath);
+        assert_eq!(uevent.subsystem, "rpmsg");
+        assert_eq!(
+            uevent.device_name(),
+            "virtio0.rpmsg-openamp-demo-channel.-1.0"
+        );
+        assert!(uevent.announces_rpmsg_device("virtio0.rpmsg-openamp-demo-channel.-1.0"));
+        assert!(!uevent.announces_rpmsg_device("virtio0.other-channel.-1.0"));
+
+        let removed = message.replacen("add@", "remove@", 1);
+        assert!(!Uevent::parse(removed.as_bytes())
+            .unwrap()
+            .announces_rpmsg_device("virtio0.rpmsg-openamp-demo-channel.-1.0"));
+        assert_eq!(Uevent::parse(b"libudev\0\xfe\xed\xca\xfe"), None);
+        assert_eq!(Uevent::parse(b""), None);
+    }
+}